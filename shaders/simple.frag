@@ -6,11 +6,24 @@ in vec3 inPos;
 out vec4 color;
 vec3 lightDir;
 
+uniform vec3 materialDiffuse;
+uniform vec3 materialSpecular;
+uniform float materialShininess;
+uniform float materialOpacity;
+uniform vec3 cameraPosition;
+
 void main()
 {   
     lightDir = normalize(vec3(0.8, -0.5, 0.9));
-    float diff = max(0.0, dot(inNormal, -lightDir));
-    color = vec4(inColor[0]*diff, inColor[1]*diff,inColor[2]*diff, inColor[3]); 
+    vec3 normal = normalize(inNormal);
+    float diff = max(0.0, dot(normal, -lightDir));
+
+    // Blinn-Phong highlight, only on the side facing the light
+    vec3 viewDir = normalize(cameraPosition - inPos);
+    vec3 halfway = normalize(viewDir - lightDir);
+    float spec = diff > 0.0 ? pow(max(0.0, dot(normal, halfway)), max(materialShininess, 1.0)) : 0.0;
+
+    vec3 diffuse = inColor.rgb * materialDiffuse * diff;
+    color = vec4(diffuse + materialSpecular * spec, inColor[3] * materialOpacity);
 
 }
- 
\ No newline at end of file
@@ -3,12 +3,15 @@
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec4 vertexColor;
 layout(location = 2) in vec3 normal;
+layout(location = 4) in vec2 uv;
 
 out vec4 inColor;
 out vec3 inNormal;
 out vec3 inPos;
+out vec2 inUV;
 uniform mat4x4 modelMatrix;
 uniform mat4x4 MVP;
+uniform mat3x3 normalMatrix;
 vec4 origin;
 
 void main()  
@@ -39,6 +42,7 @@ mat4 matrix2 = {
     gl_Position = MVP*origin;
     inPos = vec3(modelMatrix * origin);
     inColor = vertexColor;
-    inNormal = normalize(mat3(modelMatrix)*normal);
+    inNormal = normalize(normalMatrix*normal);
+    inUV = uv;
 }
 
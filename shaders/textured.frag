@@ -0,0 +1,32 @@
+#version 430 core
+
+in vec4 inColor;
+in vec3 inNormal;
+in vec3 inPos;
+in vec2 inUV;
+out vec4 color;
+vec3 lightDir;
+
+uniform vec3 materialDiffuse;
+uniform vec3 materialSpecular;
+uniform float materialShininess;
+uniform float materialOpacity;
+uniform vec3 cameraPosition;
+uniform sampler2D albedoMap;
+
+void main()
+{   
+    lightDir = normalize(vec3(0.8, -0.5, 0.9));
+    vec3 normal = normalize(inNormal);
+    float diff = max(0.0, dot(normal, -lightDir));
+
+    // Blinn-Phong highlight, only on the side facing the light
+    vec3 viewDir = normalize(cameraPosition - inPos);
+    vec3 halfway = normalize(viewDir - lightDir);
+    float spec = diff > 0.0 ? pow(max(0.0, dot(normal, halfway)), max(materialShininess, 1.0)) : 0.0;
+
+    vec4 albedo = texture(albedoMap, inUV);
+    vec3 diffuse = inColor.rgb * materialDiffuse * albedo.rgb * diff;
+    color = vec4(diffuse + materialSpecular * spec, inColor[3] * materialOpacity * albedo.a);
+
+}
@@ -0,0 +1,111 @@
+extern crate nalgebra_glm as glm;
+
+use crate::scene_graph::{EulerOrder, NodeId, SceneGraph};
+use crate::toolbox;
+
+// Animation controllers, attached to the nodes they drive as components
+
+// Spins the node about an axis through its reference point
+#[derive(Clone, Copy, Debug)]
+pub struct Rotor {
+    pub axis  : glm::Vec3,
+    pub speed : f32,            // Radians per second
+}
+
+// Flies the node around the circuit of toolbox::simple_heading_animation, centered on `start`
+#[derive(Clone, Copy, Debug)]
+pub struct FlightPath {
+    pub start         : glm::Vec3,
+    pub rest_rotation : glm::Quat,  // How the node was turned before it took off
+    pub time_offset   : f32,        // Seconds ahead of the others on the same circuit
+}
+
+impl FlightPath {
+    #[allow(dead_code)]
+    pub fn new(start: glm::Vec3) -> FlightPath {
+        FlightPath { start, rest_rotation: glm::quat_identity(), time_offset: 0.0 }
+    }
+}
+
+// Holds a FlightPath where it is, since `since` seconds after the start
+#[derive(Clone, Copy, Debug)]
+pub struct Hovering {
+    pub since : f32,
+}
+
+// Poses every node with a Rotor or a FlightPath that is not hovering for the given time since the start
+pub fn animate(scene: &mut SceneGraph, elapsed: f32) {
+    for id in scene.ids_with_component::<FlightPath>() {
+        if scene[id].components.contains::<Hovering>() {
+            continue;
+        }
+        let path = *scene[id].components.get::<FlightPath>().expect("Found by its flight path");
+        let heading = toolbox::simple_heading_animation(elapsed + path.time_offset);
+        let node = &mut scene[id];
+        node.position = path.start + glm::vec3(heading.x, 0.0, heading.z);
+        // yaw outermost, so steep pitches don't lock the roll and the yaw together
+        node.set_euler(&glm::vec3(heading.roll, heading.yaw, heading.pitch), EulerOrder::YZX);
+    }
+
+    for id in scene.ids_with_component::<Rotor>() {
+        let rotor = *scene[id].components.get::<Rotor>().expect("Found by its rotor");
+        scene[id].rotation = glm::quat_angle_axis(rotor.speed * elapsed, &rotor.axis);
+    }
+}
+
+// The node itself or the nearest of its ancestors with a FlightPath, e.g. the helicopter a rotor
+// belongs to
+pub fn flying_ancestor(scene: &SceneGraph, id: NodeId) -> Option<NodeId> {
+    let mut current = Some(id);
+    while let Some(node) = current {
+        if scene[node].components.contains::<FlightPath>() {
+            return Some(node);
+        }
+        current = scene[node].parent();
+    }
+    None
+}
+
+// Stops a flying node where it is, or sends a hovering one on along its circuit from where it stopped
+pub fn toggle_hovering(scene: &mut SceneGraph, id: NodeId, elapsed: f32) {
+    match scene[id].components.remove::<Hovering>() {
+        Some(hovering) => {
+            if let Some(path) = scene.component_mut::<FlightPath>(id) {
+                path.time_offset -= elapsed - hovering.since;
+            }
+        },
+        None => {
+            scene[id].components.insert(Hovering { since: elapsed });
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_graph::SceneNode;
+
+    #[test]
+    fn hovering_helicopters_stay_put_and_fly_on_from_where_they_stopped() {
+        let mut scene = SceneGraph::new();
+        let root = scene.root();
+        let heli = scene.add_child(root, SceneNode::new().named("heli"));
+        let rotor = scene.add_child(heli, SceneNode::new().named("rotor"));
+        scene[heli].components.insert(FlightPath::new(glm::vec3(0.0, 10.0, 0.0)));
+        assert_eq!(flying_ancestor(&scene, rotor), Some(heli));
+        assert_eq!(flying_ancestor(&scene, root), None);
+
+        animate(&mut scene, 3.0);
+        let stopped = scene[heli].position;
+        toggle_hovering(&mut scene, heli, 3.0);
+        animate(&mut scene, 8.0);
+        assert_eq!(scene[heli].position, stopped);
+
+        // Off again as if the five seconds of hovering never happened
+        toggle_hovering(&mut scene, heli, 8.0);
+        animate(&mut scene, 8.0);
+        assert!(glm::distance(&scene[heli].position, &stopped) < 1e-4);
+        animate(&mut scene, 9.0);
+        assert_ne!(scene[heli].position, stopped);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::animation::Rotor;
+use crate::mesh_registry::{MeshFiles, MeshRegistry};
+use crate::scene_graph::{NodeId, SceneGraph, SceneNode};
+
+// Models made of several moving parts, described by a small JSON file instead of code. Every part
+// is an object of one mesh file, hung below another part and pivoting about a point of its own:
+//
+// {
+//   "path": "helicopter.obj",
+//   "parts": [
+//     { "name": "body", "object": "Body_body", "color": [0.3, 0.3, 0.3, 1.0] },
+//     { "name": "tail_rotor", "object": "Tail_Rotor_tail_rotor", "parent": "body",
+//       "pivot": [0.35, 2.3, 10.4], "spin": { "axis": [1.0, 0.0, 0.0], "speed": 7.0 } }
+//   ]
+// }
+//
+// The path is relative to the descriptor. Exactly one part has no parent, and the rest of the model
+// hangs below it. Parts with a spin get a Rotor component turning them about their pivot.
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SpinEntry {
+    pub axis  : [f32; 3],
+    pub speed : f32,                        // Radians per second
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PartEntry {
+    pub name   : String,
+    pub object : String,                    // The object in the mesh file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent : Option<String>,            // Name of the part I'm attached to
+    #[serde(default)]
+    pub pivot  : [f32; 3],                  // The point I rotate about, in the model's coordinates
+    #[serde(default = "white")]
+    pub color  : [f32; 4],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spin   : Option<SpinEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ModelDescriptor {
+    pub path  : String,
+    pub parts : Vec<PartEntry>,
+
+    #[serde(skip)]
+    base_dir : PathBuf,
+}
+
+fn white() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+impl ModelDescriptor {
+
+    pub fn load(path: &str) -> Result<ModelDescriptor, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read model descriptor {}: {}", path, e))?;
+        let mut descriptor: ModelDescriptor = serde_json::from_str(&text)
+            .map_err(|e| format!("Failed to parse model descriptor {}: {}", path, e))?;
+        descriptor.base_dir = Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(descriptor)
+    }
+
+    // Builds the parts below `parent`, with the meshes taken from the registry, and returns the
+    // part at the top. The parts are put in the render layers of `parent`. A model that fails to
+    // build leaves no parts behind.
+    #[allow(dead_code)]
+    pub fn instantiate(&self, graph: &mut SceneGraph, parent: NodeId, registry: &mut MeshRegistry) -> Result<NodeId, String> {
+        self.instantiate_with(graph, parent, registry, &mut MeshFiles::default())
+    }
+
+    // Same as instantiate, sharing already parsed mesh files with the caller
+    pub fn instantiate_with(&self, graph: &mut SceneGraph, parent: NodeId, registry: &mut MeshRegistry, files: &mut MeshFiles) -> Result<NodeId, String> {
+        let path = self.base_dir.join(&self.path);
+        let mut tops = self.parts.iter().filter(|part| part.parent.is_none());
+        let top = match (tops.next(), tops.next()) {
+            (Some(top), None) => top,
+            (None, _) => return Err(format!("Model {} has no part without a parent", self.path)),
+            (Some(_), Some(_)) => return Err(format!("Model {} has more than one part without a parent", self.path)),
+        };
+
+        // Every part has to be reachable from the top through the parents, which is checked before
+        // anything is built so that a broken descriptor leaves nothing in the graph
+        let mut reached: HashSet<&str> = HashSet::new();
+        let mut stack = vec![top];
+        while let Some(part) = stack.pop() {
+            if !reached.insert(part.name.as_str()) {
+                return Err(format!("Model {} has more than one part named {}", self.path, part.name));
+            }
+            stack.extend(self.parts.iter().filter(|child| child.parent.as_deref() == Some(part.name.as_str())));
+        }
+        if let Some(orphan) = self.parts.iter().find(|part| !reached.contains(part.name.as_str())) {
+            return Err(format!("Part {} of model {} is not attached to the rest of it", orphan.name, self.path));
+        }
+
+        // Parents may be listed after their children, so the parts are placed as their parents appear.
+        // A mesh failing to load takes the parts built so far out again.
+        let layers = graph[parent].layers;
+        let mut top_id = None;
+        let mut stack = vec![(top, parent)];
+        while let Some((part, parent)) = stack.pop() {
+            let mesh = match files.load(registry, &path, Some(&part.object), part.color) {
+                Ok(mesh) => mesh,
+                Err(e) => {
+                    if let Some(top_id) = top_id {
+                        graph.despawn(top_id)?;
+                    }
+                    return Err(format!("Part {} of model {}: {}", part.name, self.path, e));
+                },
+            };
+            let mut node = SceneNode::with_mesh(mesh).named(&part.name);
+            node.reference_point = part.pivot.into();
+            node.layers = layers;
+            if let Some(spin) = &part.spin {
+                node.components.insert(Rotor { axis: spin.axis.into(), speed: spin.speed });
+            }
+            let id = graph.add_child(parent, node);
+            top_id.get_or_insert(id);
+            stack.extend(self.parts.iter().rev()
+                .filter(|child| child.parent.as_deref() == Some(part.name.as_str()))
+                .map(|child| (child, id)));
+        }
+        Ok(top_id.unwrap())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(dir: &Path, json: &str) -> ModelDescriptor {
+        let mut descriptor: ModelDescriptor = serde_json::from_str(json).unwrap();
+        descriptor.base_dir = dir.to_path_buf();
+        descriptor
+    }
+
+    #[test]
+    fn broken_descriptor_builds_nothing() {
+        let mut graph = SceneGraph::new();
+        let mut registry = MeshRegistry::without_gpu();
+        let root = graph.root();
+        let orphan = descriptor(Path::new("."), r#"{ "path": "missing.obj", "parts": [
+            { "name": "body", "object": "a" },
+            { "name": "arm", "object": "b", "parent": "nowhere" } ] }"#);
+        assert!(orphan.instantiate(&mut graph, root, &mut registry).is_err());
+        let twice = descriptor(Path::new("."), r#"{ "path": "missing.obj", "parts": [
+            { "name": "body", "object": "a" },
+            { "name": "arm", "object": "b", "parent": "body" },
+            { "name": "arm", "object": "c", "parent": "body" } ] }"#);
+        assert!(twice.instantiate(&mut graph, root, &mut registry).is_err());
+        assert_eq!(graph.len(), 1);
+        assert_eq!(registry.len(), 0);
+    }
+
+    #[test]
+    fn failing_part_takes_the_model_out_again() {
+        let dir = std::env::temp_dir().join("gloom_articulated_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("model.obj"), "o a\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let model = descriptor(&dir, r#"{ "path": "model.obj", "parts": [
+            { "name": "body", "object": "a" },
+            { "name": "arm", "object": "a", "parent": "body" },
+            { "name": "hand", "object": "b", "parent": "arm" } ] }"#);
+
+        let mut graph = SceneGraph::new();
+        let mut registry = MeshRegistry::without_gpu();
+        let root = graph.root();
+        assert!(model.instantiate(&mut graph, root, &mut registry).is_err());
+        assert_eq!(graph.len(), 1);
+        assert!(graph[root].children().is_empty());
+        assert_eq!(registry.len(), 0);
+    }
+}
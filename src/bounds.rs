@@ -0,0 +1,215 @@
+extern crate nalgebra_glm as glm;
+
+// Applies the whole affine transformation, translation included, to a point
+pub fn transform_point(matrix: &glm::Mat4, point: &glm::Vec3) -> glm::Vec3 {
+    glm::vec4_to_vec3(&(matrix * glm::vec4(point.x, point.y, point.z, 1.0)))
+}
+
+// Axis aligned bounding box
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min : glm::Vec3,
+    pub max : glm::Vec3,
+}
+
+impl Aabb {
+
+    // The smallest box around the points, or None if there are no points.
+    // `points` is a flat list of coordinates, like Mesh::vertices.
+    pub fn from_points(points: &[f32]) -> Option<Aabb> {
+        let mut chunks = points.chunks_exact(3);
+        let first = glm::make_vec3(chunks.next()?);
+        Some(chunks.fold(Aabb { min: first, max: first }, |aabb, p| {
+            let p = glm::make_vec3(p);
+            Aabb { min: glm::min2(&aabb.min, &p), max: glm::max2(&aabb.max, &p) }
+        }))
+    }
+
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    // Half of the size along each axis
+    pub fn extents(&self) -> glm::Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn merge(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: glm::min2(&self.min, &other.min),
+            max: glm::max2(&self.max, &other.max),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn contains(&self, point: &glm::Vec3) -> bool {
+        (0..3).all(|i| self.min[i] <= point[i] && point[i] <= self.max[i])
+    }
+
+    // The axis aligned box around this box after it has been transformed
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Aabb {
+        // Each axis of the new box gets the extents projected onto it through the absolute matrix
+        let center = transform_point(matrix, &self.center());
+        let linear: glm::Mat3 = glm::mat4_to_mat3(matrix);
+        let extents = linear.abs() * self.extents();
+        Aabb { min: center - extents, max: center + extents }
+    }
+
+}
+
+
+// Bounding sphere
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center : glm::Vec3,
+    pub radius : f32,
+}
+
+impl BoundingSphere {
+
+    // A sphere around the points centered on their bounding box, or None if there are no points
+    pub fn from_points(points: &[f32]) -> Option<BoundingSphere> {
+        let center = Aabb::from_points(points)?.center();
+        let radius = points.chunks_exact(3)
+            .map(|p| glm::distance(&center, &glm::make_vec3(p)))
+            .fold(0.0, f32::max);
+        Some(BoundingSphere { center, radius })
+    }
+
+    // The smallest sphere containing both spheres
+    pub fn merge(&self, other: &BoundingSphere) -> BoundingSphere {
+        let offset = other.center - self.center;
+        let distance = glm::length(&offset);
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        let center = self.center + offset * ((radius - self.radius) / distance);
+        BoundingSphere { center, radius }
+    }
+
+    // A sphere containing this one after it has been transformed. Non-uniform scaling makes it
+    // grow by the largest of the scale factors.
+    pub fn transformed(&self, matrix: &glm::Mat4) -> BoundingSphere {
+        let center = transform_point(matrix, &self.center);
+        let linear: glm::Mat3 = glm::mat4_to_mat3(matrix);
+        let scale = (0..3)
+            .map(|i| glm::length(&linear.column(i).into_owned()))
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius: self.radius * scale }
+    }
+
+}
+
+
+// Both volumes together, so users can pick whichever fits their test best
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub aabb   : Aabb,
+    pub sphere : BoundingSphere,
+}
+
+impl Bounds {
+
+    pub fn from_points(points: &[f32]) -> Option<Bounds> {
+        Some(Bounds {
+            aabb   : Aabb::from_points(points)?,
+            sphere : BoundingSphere::from_points(points)?,
+        })
+    }
+
+    pub fn merge(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            aabb   : self.aabb.merge(&other.aabb),
+            sphere : self.sphere.merge(&other.sphere),
+        }
+    }
+
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Bounds {
+        Bounds {
+            aabb   : self.aabb.transformed(matrix),
+            sphere : self.sphere.transformed(matrix),
+        }
+    }
+
+}
+
+// Merges two optional volumes, where None stands for "nothing to bound"
+pub fn merge_optional(a: Option<Bounds>, b: Option<Bounds>) -> Option<Bounds> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.merge(&b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: glm::Vec3, b: glm::Vec3) {
+        assert!(glm::distance(&a, &b) < 1e-5, "{:?} is not {:?}", a, b);
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> BoundingSphere {
+        BoundingSphere { center: glm::vec3(x, y, z), radius }
+    }
+
+    #[test]
+    fn rotated_boxes_grow_to_fit_their_corners() {
+        let aabb = Aabb::from_points(&[0.0, 0.0, 0.0, 2.0, 1.0, 1.0]).unwrap();
+        // An eighth of a turn about Z, then moved up by 10
+        let matrix = glm::translation(&glm::vec3(0.0, 10.0, 0.0))
+            * glm::rotation(std::f32::consts::FRAC_PI_4, &glm::vec3(0.0, 0.0, 1.0));
+        let rotated = aabb.transformed(&matrix);
+
+        let corners = [0.0, 2.0].iter()
+            .flat_map(|&x| [0.0, 1.0].iter().flat_map(move |&y| [0.0, 1.0].iter().map(move |&z| glm::vec3(x, y, z))));
+        let moved: Vec<f32> = corners.flat_map(|corner| {
+            let p = transform_point(&matrix, &corner);
+            vec![p.x, p.y, p.z]
+        }).collect();
+        let expected = Aabb::from_points(&moved).unwrap();
+        assert_near(rotated.min, expected.min);
+        assert_near(rotated.max, expected.max);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert_near(rotated.min, glm::vec3(-half, 10.0, 0.0));
+        assert_near(rotated.max, glm::vec3(2.0 * half, 10.0 + 3.0 * half, 1.0));
+    }
+
+    #[test]
+    fn merging_with_a_sphere_inside_keeps_the_outer_one() {
+        let outer = sphere(0.0, 0.0, 0.0, 5.0);
+        let inner = sphere(1.0, 2.0, 0.0, 1.0);
+        assert_eq!(outer.merge(&inner), outer);
+        assert_eq!(inner.merge(&outer), outer);
+    }
+
+    #[test]
+    fn merging_disjoint_spheres_just_fits_both() {
+        let merged = sphere(-3.0, 0.0, 0.0, 1.0).merge(&sphere(4.0, 0.0, 0.0, 2.0));
+        assert!((merged.radius - 5.0).abs() < 1e-6);
+        assert_near(merged.center, glm::vec3(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn merging_nothing_with_something_gives_something() {
+        let a = Bounds::from_points(&[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]).unwrap();
+        let b = Bounds::from_points(&[2.0, 2.0, 2.0, 3.0, 3.0, 3.0]).unwrap();
+        assert_eq!(merge_optional(None, None), None);
+        assert_eq!(merge_optional(Some(a), None), Some(a));
+        assert_eq!(merge_optional(None, Some(b)), Some(b));
+
+        let merged = merge_optional(Some(a), Some(b)).unwrap();
+        assert_eq!(merged, a.merge(&b));
+        assert_eq!(merged.aabb, Aabb { min: glm::vec3(0.0, 0.0, 0.0), max: glm::vec3(3.0, 3.0, 3.0) });
+        assert_near(merged.sphere.center, glm::vec3(1.5, 1.5, 1.5));
+        assert!((merged.sphere.radius - 1.5 * 3f32.sqrt()).abs() < 1e-5);
+    }
+}
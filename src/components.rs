@@ -0,0 +1,122 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+use crate::scene_graph::{NodeId, SceneGraph, SceneNode};
+
+// Typed data attached to a scene node: materials, animation controllers, tags or anything else a
+// system wants to find nodes by. A node holds at most one component of each type, and any
+// 'static type can be a component, so user data needs no wrapping.
+#[derive(Default)]
+pub struct Components {
+    map: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl Components {
+
+    // Attaches the component, handing back the one of the same type it replaced
+    pub fn insert<T: Any>(&mut self, component: T) -> Option<T> {
+        self.map.insert(TypeId::of::<T>(), Box::new(component))
+            .map(|old| *old.downcast::<T>().expect("Components are stored under their own type"))
+    }
+
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>()).and_then(|component| component.downcast_ref::<T>())
+    }
+
+    pub fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>()).and_then(|component| component.downcast_mut::<T>())
+    }
+
+    pub fn remove<T: Any>(&mut self) -> Option<T> {
+        self.map.remove(&TypeId::of::<T>())
+            .map(|old| *old.downcast::<T>().expect("Components are stored under their own type"))
+    }
+
+    pub fn contains<T: Any>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+}
+
+impl SceneGraph {
+
+    // Every node in the graph carrying a component of type T, detached ones included
+    pub fn with_component<T: Any>(&self) -> impl Iterator<Item = (NodeId, &SceneNode, &T)> {
+        self.nodes().filter_map(|(id, node)| node.components.get::<T>().map(|component| (id, node, component)))
+    }
+
+    // The ids of every node carrying a component of type T, collected so the graph can be
+    // changed while going through them
+    pub fn ids_with_component<T: Any>(&self) -> Vec<NodeId> {
+        self.with_component::<T>().map(|(id, _, _)| id).collect()
+    }
+
+    // Changing a component does not move the node, so unlike get_mut this leaves it clean
+    pub fn component_mut<T: Any>(&mut self, id: NodeId) -> Option<&mut T> {
+        self.get_mut_untracked(id)?.components.get_mut::<T>()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Debug, PartialEq)]
+    struct Label(&'static str);
+
+    #[test]
+    fn components_are_inserted_replaced_and_removed_by_type() {
+        let mut components = Components::default();
+        assert_eq!(components.insert(Health(3)), None);
+        assert_eq!(components.insert(Label("heli")), None);
+        assert_eq!(components.insert(Health(5)), Some(Health(3)));
+        assert_eq!(components.len(), 2);
+        assert_eq!(components.get::<Health>(), Some(&Health(5)));
+        assert!(components.get::<u32>().is_none());
+
+        components.get_mut::<Health>().unwrap().0 -= 1;
+        assert_eq!(components.remove::<Health>(), Some(Health(4)));
+        assert!(!components.contains::<Health>() && components.contains::<Label>());
+        assert_eq!(components.remove::<Health>(), None);
+        assert_eq!(components.len(), 1);
+    }
+
+    #[test]
+    fn graphs_are_queried_by_component_type() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let a = graph.add_child(root, SceneNode::new().named("a"));
+        let b = graph.add_child(a, SceneNode::new().named("b"));
+        let detached = graph.add_child(root, SceneNode::new().named("detached"));
+        graph.detach(detached).unwrap();
+        for &id in [a, b, detached].iter() {
+            graph[id].components.insert(Label("node"));
+        }
+        graph[b].components.insert(Health(7));
+        graph.update_transforms();
+
+        assert_eq!(graph.ids_with_component::<Label>(), vec![a, b, detached]);
+        let healthy: Vec<_> = graph.with_component::<Health>()
+            .map(|(id, node, health)| (id, node.name.clone().unwrap(), health.0))
+            .collect();
+        assert_eq!(healthy, vec![(b, "b".to_string(), 7)]);
+
+        // Changing a component in place leaves the node's transforms clean
+        graph.component_mut::<Health>(b).unwrap().0 = 2;
+        assert_eq!(graph[b].components.get::<Health>(), Some(&Health(2)));
+        assert!(!graph[b].is_dirty());
+        assert!(graph.component_mut::<Health>(a).is_none());
+
+        graph[b].components.remove::<Health>();
+        assert_eq!(graph.with_component::<Health>().count(), 0);
+        assert_eq!(graph.ids_with_component::<Label>().len(), 3);
+    }
+}
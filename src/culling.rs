@@ -0,0 +1,148 @@
+extern crate nalgebra_glm as glm;
+
+use crate::bounds::{Aabb, BoundingSphere, Bounds};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+// The six planes enclosing everything a camera can see. Each plane is stored as (normal, distance)
+// with the normal pointing into the frustum, so a point p is on the inside when
+// dot(normal, p) + distance >= 0.
+pub struct Frustum {
+    planes: [glm::Vec4; 6],
+}
+
+impl Frustum {
+
+    // Pulls the planes out of a view-projection matrix (Gribb & Hartmann). With a projection
+    // matrix alone the planes are in view space, with view-projection they are in world space.
+    pub fn from_matrix(view_projection: &glm::Mat4) -> Frustum {
+        let row = |i: usize| view_projection.row(i).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let mut planes = [w + x, w - x, w + y, w - y, w + z, w - z]; // left, right, bottom, top, near, far
+        for plane in planes.iter_mut() {
+            let length = glm::length(&plane.xyz());
+            *plane /= length;
+        }
+        Frustum { planes }
+    }
+
+    fn signed_distance(plane: &glm::Vec4, point: &glm::Vec3) -> f32 {
+        glm::dot(&plane.xyz(), point) + plane.w
+    }
+
+    pub fn classify_sphere(&self, sphere: &BoundingSphere) -> Containment {
+        let mut result = Containment::Inside;
+        for plane in &self.planes {
+            let distance = Frustum::signed_distance(plane, &sphere.center);
+            if distance < -sphere.radius {
+                return Containment::Outside;
+            }
+            if distance < sphere.radius {
+                result = Containment::Intersecting;
+            }
+        }
+        result
+    }
+
+    pub fn classify_aabb(&self, aabb: &Aabb) -> Containment {
+        let center = aabb.center();
+        let extents = aabb.extents();
+        let mut result = Containment::Inside;
+        for plane in &self.planes {
+            // How far the box reaches towards the plane, measured along its normal
+            let reach = glm::dot(&plane.xyz().abs(), &extents);
+            let distance = Frustum::signed_distance(plane, &center);
+            if distance < -reach {
+                return Containment::Outside;
+            }
+            if distance < reach {
+                result = Containment::Intersecting;
+            }
+        }
+        result
+    }
+
+    // Tries the cheap sphere first and only looks at the tighter box if the sphere is inconclusive
+    pub fn classify(&self, bounds: &Bounds) -> Containment {
+        match self.classify_sphere(&bounds.sphere) {
+            Containment::Intersecting => self.classify_aabb(&bounds.aabb),
+            containment => containment,
+        }
+    }
+
+}
+
+// How many drawable nodes the last pass drew, and how many it skipped for being out of view
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub drawn  : usize,
+    pub culled : usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A camera at the origin looking down -Z, seeing a quarter turn across, from 1 to 100 away
+    fn frustum() -> Frustum {
+        Frustum::from_matrix(&glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 1.0, 100.0))
+    }
+
+    fn sphere(center: glm::Vec3, radius: f32) -> BoundingSphere {
+        BoundingSphere { center, radius }
+    }
+
+    fn bounds(min: [f32; 3], max: [f32; 3]) -> Bounds {
+        Bounds::from_points(&[min[0], min[1], min[2], max[0], max[1], max[2]]).unwrap()
+    }
+
+    #[test]
+    fn spheres_are_classified_against_every_plane() {
+        let frustum = frustum();
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(0.0, 0.0, -10.0), 1.0)), Containment::Inside);
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(0.0, 0.0, 10.0), 1.0)), Containment::Outside);
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(0.0, 0.0, -1.0), 0.5)), Containment::Intersecting);
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(0.0, 0.0, -100.0), 0.5)), Containment::Intersecting);
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(0.0, 0.0, -102.0), 1.0)), Containment::Outside);
+        // Just outside the right and the top plane at 10 away
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(12.0, 0.0, -10.0), 1.0)), Containment::Outside);
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(0.0, 12.0, -10.0), 1.0)), Containment::Outside);
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(10.0, 0.0, -10.0), 1.0)), Containment::Intersecting);
+    }
+
+    #[test]
+    fn boxes_are_classified_against_every_plane() {
+        let frustum = frustum();
+        let aabb = |min, max| bounds(min, max).aabb;
+        assert_eq!(frustum.classify_aabb(&aabb([-1.0, -1.0, -11.0], [1.0, 1.0, -9.0])), Containment::Inside);
+        assert_eq!(frustum.classify_aabb(&aabb([-1.0, -1.0, 9.0], [1.0, 1.0, 11.0])), Containment::Outside);
+        assert_eq!(frustum.classify_aabb(&aabb([9.0, -1.0, -11.0], [11.0, 1.0, -9.0])), Containment::Intersecting);
+        assert_eq!(frustum.classify_aabb(&aabb([-500.0, -500.0, -50.0], [500.0, 500.0, -40.0])), Containment::Intersecting);
+    }
+
+    #[test]
+    fn classify_looks_at_the_box_when_the_sphere_is_unsure() {
+        let frustum = frustum();
+        // A tall thin box beside the right plane, whose sphere reaches into the view but which does not
+        let beside = bounds([11.0, -5.0, -10.5], [12.0, 5.0, -9.5]);
+        assert_eq!(frustum.classify_sphere(&beside.sphere), Containment::Intersecting);
+        assert_eq!(frustum.classify(&beside), Containment::Outside);
+
+        let ahead = bounds([-1.0, -1.0, -11.0], [1.0, 1.0, -9.0]);
+        assert_eq!(frustum.classify(&ahead), Containment::Inside);
+    }
+
+    #[test]
+    fn planes_follow_the_camera() {
+        let view = glm::look_at(&glm::vec3(50.0, 0.0, 0.0), &glm::vec3(60.0, 0.0, 0.0), &glm::vec3(0.0, 1.0, 0.0));
+        let projection = glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 1.0, 100.0);
+        let frustum = Frustum::from_matrix(&(projection * view));
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(70.0, 0.0, 0.0), 1.0)), Containment::Inside);
+        assert_eq!(frustum.classify_sphere(&sphere(glm::vec3(0.0, 0.0, -10.0), 1.0)), Containment::Outside);
+    }
+}
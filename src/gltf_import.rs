@@ -0,0 +1,337 @@
+extern crate nalgebra_glm as glm;
+
+use std::path::Path;
+
+use crate::mesh::Mesh;
+use crate::mesh_registry::{resource_name, MeshRegistry};
+use crate::scene_graph::{NodeId, SceneGraph, SceneNode};
+
+// Reads glTF 2.0 files, both .gltf with its buffers beside it and self-contained .glb. Unlike OBJ
+// they carry a node hierarchy, and every node pivots about its own origin, so models come in with
+// their parts already placed and their rotors turning about the right points.
+
+// A node of the file's hierarchy, with its transform relative to its parent
+pub struct GltfNode {
+    pub name     : Option<String>,
+    pub position : glm::Vec3,
+    pub rotation : glm::Quat,
+    pub scale    : glm::Vec3,
+    pub mesh     : Option<usize>,       // Index into GltfScene::meshes
+    pub children : Vec<GltfNode>,
+}
+
+pub struct GltfScene {
+    pub source : String,                // The file it was read from
+    pub meshes : Vec<(String, Mesh)>,   // One per mesh in the file, with all its primitives merged
+    pub nodes  : Vec<GltfNode>,         // The roots of the file's default scene
+}
+
+pub fn load_gltf(path: &str) -> Result<GltfScene, String> {
+    let gltf = gltf::Gltf::open(path)
+        .map_err(|e| format!("Failed to load {}: {}", path, e))?;
+    let base = Path::new(path).parent();
+    let buffers = gltf::import_buffers(&gltf.document, base, gltf.blob.clone())
+        .map_err(|e| format!("Failed to load the buffers of {}: {}", path, e))?;
+
+    let meshes = gltf.document.meshes()
+        .map(|mesh| {
+            let name = mesh.name().map(str::to_string).unwrap_or_else(|| format!("mesh{}", mesh.index()));
+            read_mesh(&mesh, &buffers).map(|data| (name, data))
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    let scene = gltf.document.default_scene()
+        .or_else(|| gltf.document.scenes().next())
+        .ok_or(format!("{} has no scene", path))?;
+    Ok(GltfScene {
+        source : path.to_string(),
+        meshes,
+        nodes  : scene.nodes().map(|node| read_node(&node)).collect(),
+    })
+}
+
+// The triangles of all of the mesh's primitives in one Mesh, coloured by their materials' base
+// colour, times the vertex colours if there are any. Points and lines are left out.
+fn read_mesh(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data]) -> Result<Mesh, String> {
+    let mut result = Mesh::empty();
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            continue;
+        }
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+        let positions: Vec<[f32; 3]> = reader.read_positions()
+            .ok_or(format!("A primitive of mesh {} has no positions", mesh.index()))?
+            .collect();
+        let offset = (result.vertices.len() / 3) as u32;
+        let base_color = primitive.material().pbr_metallic_roughness().base_color_factor();
+
+        match reader.read_indices() {
+            Some(indices) => result.indices.extend(indices.into_u32().map(|i| offset + i)),
+            None => result.indices.extend(offset..offset + positions.len() as u32),
+        }
+        // Either every vertex has a normal or none of them do and they are generated, so they keep
+        // lining up
+        match reader.read_normals() {
+            Some(normals) if result.normals.len() == result.vertices.len() => result.normals.extend(normals.flatten()),
+            _ => result.normals.clear(),
+        }
+        // Same for texture coordinates and tangents. glTF puts the origin of the texture at the top,
+        // where OBJ and OpenGL have it at the bottom.
+        match reader.read_tex_coords(0) {
+            Some(uvs) if result.uvs.len() / 2 == result.vertices.len() / 3 =>
+                result.uvs.extend(uvs.into_f32().flat_map(|[u, v]| [u, 1.0 - v])),
+            _ => result.uvs.clear(),
+        }
+        match reader.read_tangents() {
+            Some(tangents) if result.tangents.len() / 4 == result.vertices.len() / 3 =>
+                result.tangents.extend(tangents.flat_map(|[x, y, z, w]| [x, y, z, -w])),
+            _ => result.tangents.clear(),
+        }
+        match reader.read_colors(0) {
+            Some(colors) => result.colors.extend(colors.into_rgba_f32()
+                .flat_map(|c| (0..4).map(move |i| c[i] * base_color[i]))),
+            None => result.colors.extend(base_color.iter().cycle().take(positions.len() * 4)),
+        }
+        result.vertices.extend(positions.into_iter().flatten());
+    }
+    result.index_count = result.indices.len() as i32;
+    result.ensure_normals();
+    result.ensure_tangents();
+    Ok(result)
+}
+
+fn read_node(node: &gltf::Node) -> GltfNode {
+    let (translation, rotation, scale) = node.transform().decomposed();
+    GltfNode {
+        name     : node.name().map(str::to_string),
+        position : translation.into(),
+        rotation : glm::quat(rotation[0], rotation[1], rotation[2], rotation[3]),
+        scale    : scale.into(),
+        mesh     : node.mesh().map(|mesh| mesh.index()),
+        children : node.children().map(|child| read_node(&child)).collect(),
+    }
+}
+
+impl GltfScene {
+
+    // The named mesh. Without a name there has to be exactly one.
+    pub fn mesh(&self, name: Option<&str>) -> Result<&Mesh, String> {
+        match name {
+            Some(name) => self.meshes.iter().find(|(n, _)| n == name).map(|(_, mesh)| mesh)
+                .ok_or(format!("No mesh named {} in {}", name, self.source)),
+            None if self.meshes.len() == 1 => Ok(&self.meshes[0].1),
+            None => Err(format!("Expected a single mesh but found {}, please name one", self.meshes.len())),
+        }
+    }
+
+    // Builds the file's hierarchy below `parent`, with the meshes taken from the registry, and puts
+    // it in the render layers of `parent`. Returns the nodes added directly below `parent`,
+    // or an error with none of them left in the graph.
+    pub fn instantiate(&self, graph: &mut SceneGraph, parent: NodeId, registry: &mut MeshRegistry) -> Result<Vec<NodeId>, String> {
+        let top = parent;
+        let layers = graph[parent].layers;
+        let mut tops = vec![];
+        let mut stack: Vec<(&GltfNode, NodeId)> = self.nodes.iter().rev().map(|node| (node, parent)).collect();
+        while let Some((entry, parent)) = stack.pop() {
+            let mut node = match entry.mesh {
+                Some(index) => {
+                    let (name, mesh) = &self.meshes[index];
+                    // Named the way MeshFiles does, so that the mesh is shared with nodes asking
+                    // for it by name. Mesh names in a file need not be unique though, and the
+                    // meshes not found by their names get the index in theirs.
+                    let white = [1.0, 1.0, 1.0, 1.0];
+                    let found_by_name = self.meshes.iter().position(|(n, _)| n == name) == Some(index);
+                    let resource_name = if found_by_name {
+                        resource_name(Path::new(&self.source), Some(name), white)
+                    } else {
+                        resource_name(Path::new(&self.source), Some(&format!("{}[{}]", name, index)), white)
+                    };
+                    match registry.load(&resource_name, || Ok(mesh.clone())) {
+                        Ok(handle) => SceneNode::with_mesh(handle),
+                        Err(e) => {
+                            // Nothing is left half built
+                            for id in tops {
+                                graph.despawn(id)?;
+                            }
+                            return Err(e);
+                        },
+                    }
+                },
+                None => SceneNode::new(),
+            };
+            node.name = entry.name.clone();
+            node.position = entry.position;
+            node.rotation = entry.rotation;
+            node.scale = entry.scale;
+            node.layers = layers;
+
+            let id = graph.add_child(parent, node);
+            if parent == top {
+                tops.push(id);
+            }
+            stack.extend(entry.children.iter().rev().map(|child| (child, id)));
+        }
+        Ok(tops)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base64(bytes: &[u8]) -> String {
+        const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for chunk in bytes.chunks(3) {
+            let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    text.push(DIGITS[(bits >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    text.push('=');
+                }
+            }
+        }
+        text
+    }
+
+    // One triangle with a normal, texture coordinates and a tangent for each corner, in a two level
+    // hierarchy next to a second root, all in a single file with its buffer embedded
+    fn write_triangle_file(name: &str) -> String {
+        let positions = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let normals = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0];
+        let uvs = [0.0, 0.0, 1.0, 0.0, 0.0, 0.25];
+        let tangents = [1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, -1.0];
+        let mut buffer: Vec<u8> = positions.iter().chain(&normals).chain(&uvs).chain(&tangents)
+            .flat_map(|x: &f32| x.to_le_bytes())
+            .collect();
+        buffer.extend([0u16, 1, 2].iter().flat_map(|i| i.to_le_bytes()));
+
+        let json = format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scene": 0,
+            "scenes": [{{ "nodes": [0, 4] }}],
+            "nodes": [
+                {{ "name": "body", "mesh": 0, "children": [1, 2],
+                   "translation": [1, 2, 3], "rotation": [0, 0.6, 0, 0.8], "scale": [2, 2, 2] }},
+                {{ "name": "rotor", "mesh": 0, "translation": [0, 1, 0] }},
+                {{ "name": "tail", "children": [3] }},
+                {{ "name": "light", "translation": [0, 0, -4] }},
+                {{ "name": "ground" }}
+            ],
+            "meshes": [{{ "name": "triangle", "primitives": [{{
+                "attributes": {{ "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2, "TANGENT": 3 }},
+                "indices": 4
+            }}] }}],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                   "min": [0, 0, 0], "max": [1, 1, 0] }},
+                {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" }},
+                {{ "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2" }},
+                {{ "bufferView": 3, "componentType": 5126, "count": 3, "type": "VEC4" }},
+                {{ "bufferView": 4, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+            ],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 72, "byteLength": 24 }},
+                {{ "buffer": 0, "byteOffset": 96, "byteLength": 48 }},
+                {{ "buffer": 0, "byteOffset": 144, "byteLength": 6 }}
+            ],
+            "buffers": [{{ "byteLength": {}, "uri": "data:application/octet-stream;base64,{}" }}]
+        }}"#, buffer.len(), base64(&buffer));
+
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, json).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn load(name: &str) -> GltfScene {
+        let path = write_triangle_file(name);
+        let scene = load_gltf(&path);
+        std::fs::remove_file(&path).unwrap();
+        match scene {
+            Ok(scene) => scene,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn base64_pads_the_last_group() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+    }
+
+    #[test]
+    fn nodes_keep_their_transforms_and_order() {
+        let scene = load("gloom_gltf_nodes_test.gltf");
+        let names = |nodes: &[GltfNode]| nodes.iter().map(|node| node.name.clone().unwrap()).collect::<Vec<_>>();
+        assert_eq!(names(&scene.nodes), ["body", "ground"]);
+        assert_eq!(names(&scene.nodes[0].children), ["rotor", "tail"]);
+        assert_eq!(names(&scene.nodes[0].children[1].children), ["light"]);
+
+        let body = &scene.nodes[0];
+        assert_eq!(body.position, glm::vec3(1.0, 2.0, 3.0));
+        assert_eq!(body.rotation.coords, glm::vec4(0.0, 0.6, 0.0, 0.8));
+        assert_eq!(body.scale, glm::vec3(2.0, 2.0, 2.0));
+        assert_eq!(body.mesh, Some(0));
+        assert_eq!(body.children[0].mesh, Some(0));
+        assert_eq!(body.children[1].mesh, None);
+
+        let ground = &scene.nodes[1];
+        assert_eq!(ground.position, glm::vec3(0.0, 0.0, 0.0));
+        assert_eq!(ground.rotation, glm::quat_identity());
+        assert_eq!(ground.scale, glm::vec3(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn textures_are_flipped_to_have_their_origin_at_the_bottom() {
+        let scene = load("gloom_gltf_mesh_test.gltf");
+        let mesh = match scene.mesh(None) {
+            Ok(mesh) => mesh,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(scene.meshes[0].0, "triangle");
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.index_count, 3);
+        assert_eq!(mesh.normals, [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(mesh.uvs, [0.0, 1.0, 1.0, 1.0, 0.0, 0.75]);
+        // With V flipped the bitangents turn round, and their signs with them
+        assert_eq!(mesh.tangents, [1.0, 0.0, 0.0, -1.0, 1.0, 0.0, 0.0, -1.0, 1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(mesh.colors, [1.0; 12]);
+    }
+
+    #[test]
+    fn instantiating_builds_the_hierarchy_in_file_order() {
+        let scene = load("gloom_gltf_instantiate_test.gltf");
+        let mut graph = SceneGraph::new();
+        let mut registry = MeshRegistry::without_gpu();
+        let root = graph.root();
+        graph[root].layers = 0b10;
+        let tops = match scene.instantiate(&mut graph, root, &mut registry) {
+            Ok(tops) => tops,
+            Err(e) => panic!("{}", e),
+        };
+
+        let name = |id: NodeId| graph[id].name.clone().unwrap();
+        assert_eq!(tops.iter().map(|&id| name(id)).collect::<Vec<_>>(), ["body", "ground"]);
+        assert_eq!(graph[root].children(), &tops[..]);
+        let body = tops[0];
+        assert_eq!(graph[body].children().iter().map(|&id| name(id)).collect::<Vec<_>>(), ["rotor", "tail"]);
+        assert_eq!(graph[body].position, glm::vec3(1.0, 2.0, 3.0));
+        assert_eq!(graph[body].scale, glm::vec3(2.0, 2.0, 2.0));
+
+        let rotor = graph.find_path(body, "rotor").unwrap();
+        let light = graph.find_path(body, "tail/light").unwrap();
+        assert_eq!(graph[light].position, glm::vec3(0.0, 0.0, -4.0));
+        assert!(graph[light].mesh.is_none());
+        assert!(graph.nodes().filter(|&(id, _)| id != root).all(|(_, node)| node.layers == 0b10));
+        // Both nodes drawing the triangle share it
+        let (body_mesh, rotor_mesh) = (graph[body].mesh.clone().unwrap(), graph[rotor].mesh.clone().unwrap());
+        assert!(std::rc::Rc::ptr_eq(&body_mesh, &rotor_mesh));
+        assert_eq!(registry.len(), 1);
+    }
+}
@@ -0,0 +1,248 @@
+extern crate nalgebra_glm as glm;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mesh::Mesh;
+
+// Terrain made from a grayscale image, one vertex per pixel with the brightness as its height, so
+// that a new landing site only needs a heightmap instead of a model. 8 and 16 bit images both work,
+// with white as the highest point either way. The terrain is centred on the origin in the XZ plane,
+// with the top of the image towards -Z, and the whole image textured over it.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct HeightmapOptions {
+    #[serde(default = "one")]
+    pub spacing      : f32,             // Distance between neighbouring pixels
+    #[serde(default = "one")]
+    pub height_scale : f32,             // Height of white, black being at 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skirt        : Option<f32>,     // How far down to hang walls from the edges, hiding the underside
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+impl Default for HeightmapOptions {
+    fn default() -> Self {
+        HeightmapOptions { spacing: 1.0, height_scale: 1.0, skirt: None }
+    }
+}
+
+pub fn load_heightmap(path: &str, options: &HeightmapOptions) -> Result<Mesh, String> {
+    let image = image::open(path).map_err(|e| format!("Failed to load heightmap {}: {}", path, e))?;
+    // 8 bit images are stretched to 16 bits, so both come out between 0 and 1 the same way
+    let pixels = image.into_luma16();
+    let (width, depth) = pixels.dimensions();
+    let heights: Vec<f32> = pixels.pixels().map(|pixel| pixel.0[0] as f32 / u16::MAX as f32).collect();
+    heightmap_mesh(&heights, width, depth, options).map_err(|e| format!("Heightmap {}: {}", path, e))
+}
+
+// The terrain for `heights` between 0 and 1, given row by row from the top of the image, in white.
+// It takes at least 2x2 heights to make a surface.
+pub fn heightmap_mesh(heights: &[f32], width: u32, depth: u32, options: &HeightmapOptions) -> Result<Mesh, String> {
+    let (w, d) = (width as usize, depth as usize);
+    if w < 2 || d < 2 {
+        return Err(format!("{}x{} heights are too few, it takes at least 2x2", width, depth));
+    }
+    if heights.len() != w * d {
+        return Err(format!("{} heights do not make a {}x{} grid", heights.len(), width, depth));
+    }
+    let height = |i: usize, j: usize| heights[j * w + i] * options.height_scale;
+    let position = |i: usize, j: usize| glm::vec3(
+        (i as f32 - (w - 1) as f32 / 2.0) * options.spacing,
+        height(i, j),
+        (j as f32 - (d - 1) as f32 / 2.0) * options.spacing,
+    );
+    // The slope from the neighbours on either side, or the one neighbour there is at the edges
+    let normal = |i: usize, j: usize| {
+        let (left, right) = (i.saturating_sub(1), (i + 1).min(w - 1));
+        let (up, down) = (j.saturating_sub(1), (j + 1).min(d - 1));
+        let dx = (height(right, j) - height(left, j)) / ((right - left) as f32 * options.spacing);
+        let dz = (height(i, down) - height(i, up)) / ((down - up) as f32 * options.spacing);
+        glm::normalize(&glm::vec3(-dx, 1.0, -dz))
+    };
+    let uv = |i: usize, j: usize| [i as f32 / (w - 1) as f32, 1.0 - j as f32 / (d - 1) as f32];
+
+    let mut mesh = Mesh::empty();
+    for j in 0..d {
+        for i in 0..w {
+            mesh.vertices.extend(position(i, j).iter());
+            mesh.normals.extend(normal(i, j).iter());
+            mesh.uvs.extend(uv(i, j));
+            mesh.colors.extend([1.0, 1.0, 1.0, 1.0]);
+        }
+    }
+    let at = |i: usize, j: usize| (j * w + i) as u32;
+    for j in 0..d - 1 {
+        for i in 0..w - 1 {
+            mesh.indices.extend([at(i, j), at(i, j + 1), at(i + 1, j + 1)]);
+            mesh.indices.extend([at(i, j), at(i + 1, j + 1), at(i + 1, j)]);
+        }
+    }
+
+    if let Some(skirt) = options.skirt {
+        // Each side gets its own wall with its own vertices, facing straight out
+        let sides: [(Vec<(usize, usize)>, glm::Vec3); 4] = [
+            ((0..w).map(|i| (i, 0)).collect(), glm::vec3(0.0, 0.0, -1.0)),
+            ((0..w).map(|i| (i, d - 1)).collect(), glm::vec3(0.0, 0.0, 1.0)),
+            ((0..d).map(|j| (0, j)).collect(), glm::vec3(-1.0, 0.0, 0.0)),
+            ((0..d).map(|j| (w - 1, j)).collect(), glm::vec3(1.0, 0.0, 0.0)),
+        ];
+        for (edge, outward) in sides.iter() {
+            // The edges run along +X or +Z, so which way round the wall's triangles go depends on
+            // the side
+            let along = glm::vec3((edge[1].0 - edge[0].0) as f32, 0.0, (edge[1].1 - edge[0].1) as f32);
+            let forwards = glm::dot(&glm::cross(&along, &glm::vec3(0.0, 1.0, 0.0)), outward) > 0.0;
+            let first = (mesh.vertices.len() / 3) as u32;
+            for &(i, j) in edge {
+                for drop in [0.0, skirt] {
+                    mesh.vertices.extend((position(i, j) - glm::vec3(0.0, drop, 0.0)).iter());
+                    mesh.normals.extend(outward.iter());
+                    mesh.uvs.extend(uv(i, j));
+                    mesh.colors.extend([1.0, 1.0, 1.0, 1.0]);
+                }
+            }
+            for k in 0..edge.len() as u32 - 1 {
+                let (top, bottom, next_top, next_bottom) = (first + 2 * k, first + 2 * k + 1, first + 2 * k + 2, first + 2 * k + 3);
+                if forwards {
+                    mesh.indices.extend([top, bottom, next_bottom, top, next_bottom, next_top]);
+                } else {
+                    mesh.indices.extend([top, next_bottom, bottom, top, next_top, next_bottom]);
+                }
+            }
+        }
+    }
+
+    mesh.index_count = mesh.indices.len() as i32;
+    mesh.ensure_tangents();
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(mesh: &Mesh, vertex: u32) -> glm::Vec3 {
+        glm::make_vec3(&mesh.vertices[3 * vertex as usize..3 * vertex as usize + 3])
+    }
+
+    fn normal(mesh: &Mesh, vertex: u32) -> glm::Vec3 {
+        glm::make_vec3(&mesh.normals[3 * vertex as usize..3 * vertex as usize + 3])
+    }
+
+    fn face(mesh: &Mesh, triangle: &[u32]) -> glm::Vec3 {
+        let [a, b, c] = [position(mesh, triangle[0]), position(mesh, triangle[1]), position(mesh, triangle[2])];
+        glm::cross(&(b - a), &(c - a))
+    }
+
+    fn mesh(heights: &[f32], width: u32, depth: u32, options: &HeightmapOptions) -> Mesh {
+        match heightmap_mesh(heights, width, depth, options) {
+            Ok(mesh) => mesh,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn grids_have_a_vertex_per_height_centred_on_the_origin() {
+        let options = HeightmapOptions { spacing: 2.0, height_scale: 10.0, skirt: None };
+        let heights: Vec<f32> = (0..12).map(|k| k as f32 / 11.0).collect();
+        let terrain = mesh(&heights, 4, 3, &options);
+        assert_eq!(terrain.vertices.len(), 3 * 12);
+        assert_eq!(terrain.indices.len(), 3 * 2 * 6);
+        assert_eq!(terrain.index_count as usize, terrain.indices.len());
+        assert!(terrain.indices.iter().all(|&i| i < 12));
+        assert_eq!(terrain.tangents.len(), 4 * 12);
+        assert_eq!(position(&terrain, 0), glm::vec3(-3.0, 0.0, -2.0));
+        assert_eq!(position(&terrain, 11), glm::vec3(3.0, 10.0, 2.0));
+        assert_eq!(position(&terrain, 5), glm::vec3(-1.0, 50.0 / 11.0, 0.0));
+    }
+
+    #[test]
+    fn too_few_or_mismatched_heights_are_errors() {
+        let options = HeightmapOptions { skirt: Some(1.0), ..Default::default() };
+        assert!(heightmap_mesh(&[0.0; 3], 1, 3, &options).is_err());
+        assert!(heightmap_mesh(&[0.0; 3], 3, 1, &options).is_err());
+        assert!(heightmap_mesh(&[], 0, 0, &options).is_err());
+        assert!(heightmap_mesh(&[0.0; 5], 2, 3, &options).is_err());
+        assert!(heightmap_mesh(&[0.0; 7], 2, 3, &options).is_err());
+        assert!(heightmap_mesh(&[0.0; 6], 2, 3, &options).is_ok());
+    }
+
+    #[test]
+    fn flat_maps_face_straight_up() {
+        let terrain = mesh(&[0.5; 9], 3, 3, &HeightmapOptions::default());
+        for vertex in 0..9 {
+            assert_eq!(normal(&terrain, vertex), glm::vec3(0.0, 1.0, 0.0));
+        }
+        for triangle in terrain.indices.chunks_exact(3) {
+            let face = glm::normalize(&face(&terrain, triangle));
+            assert!((face - glm::vec3(0.0, 1.0, 0.0)).norm() < 1e-6, "{:?} faces {:?}", triangle, face);
+        }
+    }
+
+    #[test]
+    fn slopes_tip_their_normals_downhill() {
+        // Rising towards +X by one per step
+        let heights = [0.0, 1.0, 2.0, 0.0, 1.0, 2.0];
+        let terrain = mesh(&heights, 3, 2, &HeightmapOptions::default());
+        let expected = glm::normalize(&glm::vec3(-1.0, 1.0, 0.0));
+        for vertex in 0..6 {
+            assert!((normal(&terrain, vertex) - expected).norm() < 1e-6);
+        }
+        for triangle in terrain.indices.chunks_exact(3) {
+            assert!(glm::dot(&face(&terrain, triangle), &expected) > 0.0, "{:?} faces down", triangle);
+        }
+    }
+
+    #[test]
+    fn uvs_stretch_the_image_with_its_top_towards_minus_z() {
+        let terrain = mesh(&[0.0; 6], 3, 2, &HeightmapOptions::default());
+        let uv = |vertex: usize| [terrain.uvs[2 * vertex], terrain.uvs[2 * vertex + 1]];
+        assert_eq!(uv(0), [0.0, 1.0]);
+        assert_eq!(uv(1), [0.5, 1.0]);
+        assert_eq!(uv(2), [1.0, 1.0]);
+        assert_eq!(uv(3), [0.0, 0.0]);
+        assert_eq!(uv(5), [1.0, 0.0]);
+        assert!(position(&terrain, 0).z < position(&terrain, 3).z);
+    }
+
+    #[test]
+    fn skirts_hang_an_outward_wall_from_every_edge() {
+        let options = HeightmapOptions { skirt: Some(0.5), ..Default::default() };
+        let heights: Vec<f32> = (0..12).map(|k| k as f32 / 11.0).collect();
+        let plain = mesh(&heights, 4, 3, &HeightmapOptions::default());
+        let skirted = mesh(&heights, 4, 3, &options);
+
+        // Two vertices for each point along each of the four edges, and two triangles per step
+        // along them, after the grid itself
+        assert_eq!(skirted.vertices.len() / 3, 12 + 2 * (4 + 4 + 3 + 3));
+        assert_eq!(skirted.indices.len(), plain.indices.len() + 6 * (3 + 3 + 2 + 2));
+        assert_eq!(skirted.indices[..plain.indices.len()], plain.indices[..]);
+        assert_eq!(skirted.vertices[..plain.vertices.len()], plain.vertices[..]);
+        let walls = &skirted.indices[plain.indices.len()..];
+        assert!(walls.iter().all(|&i| (12..40).contains(&i)));
+
+        for vertex in (12..40).step_by(2) {
+            let (top, bottom) = (position(&skirted, vertex), position(&skirted, vertex + 1));
+            assert_eq!(top - bottom, glm::vec3(0.0, 0.5, 0.0));
+            assert_eq!(normal(&skirted, vertex), normal(&skirted, vertex + 1));
+            // The top of the wall sits on the edge of the grid
+            assert!(plain.vertices.chunks_exact(3).any(|p| glm::make_vec3(p) == top));
+        }
+        for triangle in walls.chunks_exact(3) {
+            let outward = normal(&skirted, triangle[0]);
+            assert_eq!(outward.y, 0.0);
+            assert!(glm::dot(&outward, &position(&skirted, triangle[0])) > 0.0);
+            assert!(glm::dot(&face(&skirted, triangle), &outward) > 0.0, "{:?} faces in", triangle);
+        }
+    }
+
+    #[test]
+    fn the_smallest_maps_still_get_skirts() {
+        let options = HeightmapOptions { skirt: Some(1.0), ..Default::default() };
+        let terrain = mesh(&[0.0; 4], 2, 2, &options);
+        assert_eq!(terrain.vertices.len() / 3, 4 + 2 * 8);
+        assert_eq!(terrain.indices.len(), 6 + 4 * 6);
+    }
+}
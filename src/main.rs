@@ -1,24 +1,48 @@
 extern crate nalgebra_glm as glm;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::{f32::consts, mem, os::raw::c_void, ptr};
-
+use std::{mem, os::raw::c_void, ptr};
+
+mod animation;
+mod articulated;
+mod bounds;
+mod components;
+mod culling;
+mod gltf_import;
+mod heightmap;
 mod mesh;
+mod mesh_registry;
+mod normals;
+mod obj_export;
+mod picking;
+mod ply;
+mod primitives;
+mod scene_dump;
+mod scene_file;
 mod scene_graph;
 mod toolbox;
+mod traversal;
 mod shader;
+mod stl;
+mod tangents;
+mod texture;
 mod util;
 
 
 use glutin::event::{
     DeviceEvent,
     ElementState::{Pressed, Released},
-    Event, KeyboardInput,
+    Event, KeyboardInput, MouseButton,
     VirtualKeyCode::{self, *},
     WindowEvent,
 };
 use glutin::event_loop::ControlFlow;
-use scene_graph::SceneNode;
+use mesh::Material;
+use mesh_registry::{GpuMesh, MeshRegistry};
+use culling::{Containment, CullStats, Frustum};
+use picking::Ray;
+use texture::{SamplerSettings, TextureCache};
+use scene_graph::{NodeId, SceneGraph, LAYER_ALL, LAYER_TERRAIN, LAYER_VEHICLES};
 
 //let mut global_transformation_matrix: glm:: Mat4 = glm::identity()
 
@@ -34,13 +58,13 @@ const INITIAL_SCREEN_H: u32 = 600;
 // Get the size of an arbitrary array of numbers measured in bytes
 // Example usage:  pointer_to_array(my_array)
 fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
+    std::mem::size_of_val(val) as isize
 }
 
 // Get the OpenGL-compatible pointer to an arbitrary array of numbers
 // Example usage:  pointer_to_array(my_array)
 fn pointer_to_array<T>(val: &[T]) -> *const c_void {
-    &val[0] as *const T as *const c_void
+    val.as_ptr() as *const c_void
 }
 
 // Get the size of the given type in bytes
@@ -56,7 +80,7 @@ fn offset<T>(n: u32) -> *const c_void {
 }
 
 
-unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>, colors: &Vec<f32>, normals: &Vec<f32>) -> u32 {
+unsafe fn create_vao(vertices: &[f32], indices: &[u32], colors: &[f32], normals: &[f32], tangents: &[f32], uvs: &[f32]) -> GpuMesh {
     
     let mut array: u32 = 0; //creation of the variable
     gl::GenVertexArrays(1, &mut array); //creation of the VAO use the ID to refer to the array 
@@ -80,8 +104,8 @@ unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>, colors: &Vec<f32>,
     gl::BindBuffer(gl::ARRAY_BUFFER, cbo);
     gl::BufferData(
         gl::ARRAY_BUFFER,
-        byte_size_of_array(&colors),
-        pointer_to_array(&colors),
+        byte_size_of_array(colors),
+        pointer_to_array(colors),
         gl::STATIC_DRAW,
     );
 
@@ -102,8 +126,8 @@ unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>, colors: &Vec<f32>,
      gl::BindBuffer(gl::ARRAY_BUFFER, normal_vbo);
      gl::BufferData(
          gl::ARRAY_BUFFER,
-         byte_size_of_array(&normals),
-         pointer_to_array(&normals),
+         byte_size_of_array(normals),
+         pointer_to_array(normals),
          gl::STATIC_DRAW,
      );
 
@@ -116,61 +140,171 @@ unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>, colors: &Vec<f32>,
         ptr::null());
     gl::EnableVertexAttribArray(2);
 
+    // tangent buffer, left out for meshes without texture coordinates. The shader then reads the
+    // attribute's default (0, 0, 0, 1)
+    let mut tangent_vbo: u32 = 0;
+    if !tangents.is_empty() {
+        gl::GenBuffers(1, &mut tangent_vbo);
+        gl::BindBuffer(gl::ARRAY_BUFFER, tangent_vbo);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            byte_size_of_array(tangents),
+            pointer_to_array(tangents),
+            gl::STATIC_DRAW,
+        );
+        gl::VertexAttribPointer(3, 4, gl::FLOAT, gl::FALSE, size_of::<f32>() * 4, ptr::null());
+        gl::EnableVertexAttribArray(3);
+    }
+
+    // texture coordinate buffer, left out the same way. The shader then reads (0, 0)
+    let mut uv_vbo: u32 = 0;
+    if !uvs.is_empty() {
+        gl::GenBuffers(1, &mut uv_vbo);
+        gl::BindBuffer(gl::ARRAY_BUFFER, uv_vbo);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            byte_size_of_array(uvs),
+            pointer_to_array(uvs),
+            gl::STATIC_DRAW,
+        );
+        gl::VertexAttribPointer(4, 2, gl::FLOAT, gl::FALSE, size_of::<f32>() * 2, ptr::null());
+        gl::EnableVertexAttribArray(4);
+    }
+
 //second chapter--> specify information about how these are supposed to be combined
 
     let mut second_buffer_id: u32 = 0;
     gl::GenBuffers(1, &mut second_buffer_id); //buffer of indices
     gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, second_buffer_id); //special status therefore ELEMENT_ARRAY_BUFFER
-    gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, byte_size_of_array(&indices), pointer_to_array(&indices), gl::STATIC_DRAW,); //target ELEMENT_ARRAY_BUFFER too
+    gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, byte_size_of_array(indices), pointer_to_array(indices), gl::STATIC_DRAW,); //target ELEMENT_ARRAY_BUFFER too
 
-    return array
+    let buffers = vec![buffer_ids, cbo, normal_vbo, tangent_vbo, uv_vbo, second_buffer_id];
+    GpuMesh::new(array, indices.len() as i32, buffers.into_iter().filter(|&buffer| buffer != 0).collect())
 }
 
-unsafe fn draw_scene(
-    node: &mut SceneNode,
-    view_projection_matrix: &glm::Mat4,
-    transformation_so_far: &glm::Mat4,
-    MVP: i32,
-    modelMatrix: i32
+// Where a shader program takes what draw_scene tells it about the node being drawn
+#[derive(Clone, Copy)]
+struct ProgramLocations {
+    program_id: u32,
+    mvp: i32,
+    model_matrix: i32,
+    normal_matrix: i32,
+    camera_position: i32,
+    material_diffuse: i32,
+    material_specular: i32,
+    material_shininess: i32,
+    material_opacity: i32,
+}
+
+impl ProgramLocations {
+    unsafe fn find(shader: &shader::Shader) -> ProgramLocations {
+        ProgramLocations {
+            program_id: shader.program_id,
+            mvp: shader.get_uniform_location("MVP"),
+            model_matrix: shader.get_uniform_location("modelMatrix"),
+            normal_matrix: shader.get_uniform_location("normalMatrix"),
+            camera_position: shader.get_uniform_location("cameraPosition"),
+            material_diffuse: shader.get_uniform_location("materialDiffuse"),
+            material_specular: shader.get_uniform_location("materialSpecular"),
+            material_shininess: shader.get_uniform_location("materialShininess"),
+            material_opacity: shader.get_uniform_location("materialOpacity"),
+        }
+    }
 
+    unsafe fn set_material(&self, material: &Material) {
+        gl::Uniform3fv(self.material_diffuse, 1, material.diffuse.as_ptr());
+        gl::Uniform3fv(self.material_specular, 1, material.specular.as_ptr());
+        gl::Uniform1f(self.material_shininess, material.shininess);
+        gl::Uniform1f(self.material_opacity, material.opacity);
+    }
+}
+
+// Everything draw_scene needs besides the scene itself, bundled so it can be passed down the recursion
+struct DrawContext {
+    view_projection_matrix: glm::Mat4,
+    frustum: Frustum,
+    simple: ProgramLocations,       // For meshes without a texture
+    textured: ProgramLocations,     // For meshes with one
+    active_program: u32,
+    layer_mask: u32,
+    stats: CullStats,
+}
+
+// How many nodes below and including this one the pass would draw if they were all in view
+fn count_drawable(scene: &SceneGraph, node_id: NodeId, layer_mask: u32) -> usize {
+    let node = &scene[node_id];
+    if !node.visible {
+        return 0;
+    }
+    let own = if node.mesh.is_some() && node.layers & layer_mask != 0 { 1 } else { 0 };
+    own + node.children().iter().map(|&child| count_drawable(scene, child, layer_mask)).sum::<usize>()
+}
+
+// Draws the node and everything below it, skipping whole subtrees whose bounds are out of view.
+// `inside` means an ancestor was found to be entirely in view, so there is nothing left to test.
+// Drawable nodes without bounds are never culled on their own, only along with a parent's subtree.
+// Hidden nodes take their subtree with them, while nodes outside the pass' layers only skip themselves.
+unsafe fn draw_scene(
+    scene: &SceneGraph,
+    node_id: NodeId,
+    context: &mut DrawContext,
+    inside: bool,
 ) {
-    let mut global_transformation_matrix: glm:: Mat4 = glm::identity();
-    let mut trans: glm::Mat4 = glm::identity();
-    let pos = glm::translation(&node.position);
-    let rotation_x = glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0));
-    let rotation_y = glm::rotation(node.rotation.y, &glm::vec3(0.0, 1.0, 0.0));
-    let rotation_z = glm::rotation(node.rotation.z, &glm::vec3(0.0, 0.0, 1.0));
-    let reference_point = glm::translation(&node.reference_point);
-    let origin_reference_p =  glm::translation(&(glm::diagonal3x3(&glm::vec3(-1.0, -1.0, -1.0)) * node.reference_point));
-
-    trans = origin_reference_p * trans;
-    
-    trans = rotation_x * rotation_y * rotation_z * trans; // rotation
-    trans = reference_point * trans;
-    trans = pos * trans;
-    global_transformation_matrix = transformation_so_far * trans;
+    let node = &scene[node_id];
+    if !node.visible {
+        return;
+    }
+    let mut inside = inside;
+    if !inside {
+        match node.subtree_bounds().map(|bounds| context.frustum.classify(bounds)) {
+            Some(Containment::Outside) => {
+                context.stats.culled += count_drawable(scene, node_id, context.layer_mask);
+                return;
+            }
+            Some(Containment::Inside) => inside = true,
+            _ => {}
+        }
+    }
+    let visible = inside || node.world_bounds()
+        .is_none_or(|bounds| context.frustum.classify(bounds) != Containment::Outside);
+
+    let global_transformation_matrix = node.world_matrix();
     // Check node
-    if node.index_count > 0 {
-        let new_trans_mat = view_projection_matrix * global_transformation_matrix;
-        gl::UniformMatrix4fv(MVP, 1, gl::FALSE, new_trans_mat.as_ptr());
+    let drawable = node.mesh.as_ref().filter(|_| node.layers & context.layer_mask != 0);
+    if drawable.is_some() && !visible {
+        context.stats.culled += 1;
+    } else if let Some(mesh) = drawable {
+        context.stats.drawn += 1;
+        let program = if mesh.gpu.texture.is_some() { context.textured } else { context.simple };
+        if context.active_program != program.program_id {
+            gl::UseProgram(program.program_id);
+            context.active_program = program.program_id;
+        }
+        if let Some(texture) = &mesh.gpu.texture {
+            texture.bind(0);
+        }
+        let new_trans_mat = context.view_projection_matrix * global_transformation_matrix;
+        gl::UniformMatrix4fv(program.mvp, 1, gl::FALSE, new_trans_mat.as_ptr());
         gl::UniformMatrix4fv(
-            modelMatrix,
+            program.model_matrix,
             1,
             gl::FALSE,
             global_transformation_matrix.as_ptr(),
         );
+        gl::UniformMatrix3fv(program.normal_matrix, 1, gl::FALSE, node.normal_matrix().as_ptr());
+        program.set_material(&mesh.mesh.material);
 
-        gl::BindVertexArray(node.vao_id);
+        gl::BindVertexArray(mesh.gpu.vao_id);
         gl::DrawElements(
             gl::TRIANGLES,
-            node.index_count, // Here we get the amount of indices we need
+            mesh.gpu.index_count, // Here we get the amount of indices we need
             gl::UNSIGNED_INT,
             ptr::null(),
         );
     }
 
-    for &child in &node.children {
-        draw_scene(&mut *child, view_projection_matrix, &global_transformation_matrix ,MVP, modelMatrix);
+    for &child in node.children() {
+        draw_scene(scene, child, context, inside);
     }
 }
 
@@ -180,7 +314,6 @@ unsafe fn draw_scene(
 fn main() {
     
     // Set up the necessary objects to deal with windows and event handling
-    let mut trans: glm::Mat4 = glm::identity();
     let el = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
         .with_title("Gloom-rs")
@@ -191,12 +324,14 @@ fn main() {
     // Uncomment these if you want to use the mouse for controls, but want it to be confined to the screen and/or invisible.
     // windowed_context.window().set_cursor_grab(true).expect("failed to grab cursor");
     // windowed_context.window().set_cursor_visible(false);
-    let mut window_aspect_ratio = INITIAL_SCREEN_W as f32 / INITIAL_SCREEN_H as f32;
+    let _window_aspect_ratio = INITIAL_SCREEN_W as f32 / INITIAL_SCREEN_H as f32;
     // Set up a shared vector for keeping track of currently pressed keys
     let arc_pressed_keys = Arc::new(Mutex::new(Vec::<VirtualKeyCode>::with_capacity(10)));
     // Make a reference of this vector to send to the render thread
     let pressed_keys = Arc::clone(&arc_pressed_keys);
-    let arc_window_size = Arc::new(Mutex::new((INITIAL_SCREEN_W, INITIAL_SCREEN_H, false)));
+    // Set up a shared tuple for the window size in physical pixels, and whether it has changed
+    let initial_size = windowed_context.window().inner_size();
+    let arc_window_size = Arc::new(Mutex::new((initial_size.width, initial_size.height, false)));
     // Make a reference of this tuple to send to the render thread
     let window_size = Arc::clone(&arc_window_size);
 
@@ -206,6 +341,12 @@ fn main() {
     // Make a reference of this tuple to send to the render thread
     let mouse_delta = Arc::clone(&arc_mouse_delta);
 
+    // Set up a shared slot for the last clicked cursor position, in physical pixels, to pick from
+    let arc_pick_request = Arc::new(Mutex::new(None::<(f32, f32)>));
+    // Make a reference of this slot to send to the render thread
+    let pick_request = Arc::clone(&arc_pick_request);
+    let mut cursor_position = (0f32, 0f32);
+
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
         // Acquire the OpenGL Context and load the function pointers. This has to be done inside of the rendering thread, because
@@ -323,17 +464,6 @@ fn main() {
         //let mut helicopter_vao_main_rotor: u32;
         //let mut helicopter_vao_tail_rotor: u32;
         
-        let terrain_vao: u32;
-
-        
-        
-        
-        //new vaos---> for task 6---> vector for each one instead of u32 variables
-        let mut helicopter_vao_body: Vec<u32> = vec![];
-        let mut helicopter_vao_main_rotor: Vec<u32> = vec![];
-        let mut helicopter_vao_tail_rotor: Vec<u32> = vec![];
-        let mut helicopter_vao_door: Vec<u32> = vec![];
-        let total_helicopters =10;
     
 
         // initially I did this before setting the vao of the helicopter parts---> +1000 helicopters were drawn for each execution
@@ -349,22 +479,28 @@ fn main() {
 
 
 
-        let terrain_moon = mesh::Terrain::load(".\\resources\\lunarsurface.obj");
-        let helicopter_moon = mesh::Helicopter::load(".\\resources\\helicopter.obj");
-        let simple_shader: shader::Shader;
         let simple_shader = unsafe {
             shader::ShaderBuilder::new()
                .attach_file(".\\shaders\\simple.vert")
                .attach_file(".\\shaders\\simple.frag")
                .link()
            };
+        // The same lighting, with the diffuse colour looked up in the material's texture
+        let textured_shader = unsafe {
+            shader::ShaderBuilder::new()
+               .attach_file(".\\shaders\\simple.vert")
+               .attach_file(".\\shaders\\textured.frag")
+               .link()
+           };
        unsafe {simple_shader.activate();}
         
-        let MVP: i32;
-        let modelMatrix: i32;
+        let simple_locations: ProgramLocations;
+        let textured_locations: ProgramLocations;
         unsafe {
-            MVP = simple_shader.get_uniform_location("MVP");
-            modelMatrix = simple_shader.get_uniform_location("modelMatrix");
+            simple_locations = ProgramLocations::find(&simple_shader);
+            textured_shader.activate();
+            textured_locations = ProgramLocations::find(&textured_shader);
+            gl::Uniform1i(textured_shader.get_uniform_location("albedoMap"), 0);
             simple_shader.activate();
         }
         
@@ -468,95 +604,29 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
 
 //extending the solution to more than one helicopter
         
-        let mut helicopter_nodes: Vec<scene_graph::Node> = vec![];
-        unsafe {
-            terrain_vao = create_vao(
-                &terrain_moon.vertices,
-                &terrain_moon.indices,
-                &terrain_moon.colors,
-                &terrain_moon.normals,
-            );
-            for i in 0..total_helicopters {
-                helicopter_vao_body.push(create_vao(
-                    &helicopter_moon.body.vertices,
-                    &helicopter_moon.body.indices,
-                    &helicopter_moon.body.colors,
-                    &helicopter_moon.body.normals,
-                ));
-                helicopter_vao_main_rotor.push(create_vao(
-                    &helicopter_moon.main_rotor.vertices,
-                    &helicopter_moon.main_rotor.indices,
-                    &helicopter_moon.main_rotor.colors,
-                    &helicopter_moon.main_rotor.normals,
-                ));
-                helicopter_vao_tail_rotor.push(create_vao(
-                    &helicopter_moon.tail_rotor.vertices,
-                    &helicopter_moon.tail_rotor.indices,
-                    &helicopter_moon.tail_rotor.colors,
-                    &helicopter_moon.tail_rotor.normals,
-                ));
-                helicopter_vao_door.push(create_vao(
-                    &helicopter_moon.door.vertices,
-                    &helicopter_moon.door.indices,
-                    &helicopter_moon.door.colors,
-                    &helicopter_moon.door.normals,
-                ));
-            }
-
-
-        }
-        
-    let mut root_node = SceneNode::new();
-    let mut terrain_node = SceneNode::from_vao(terrain_vao, terrain_moon.index_count);
-    
-    for i in 0..total_helicopters {
-        let mut helicopter_body_node = SceneNode::from_vao(
-            helicopter_vao_body[i as usize],
-            helicopter_moon.body.index_count,
-        );
-        let mut helicopter_door_node = SceneNode::from_vao(
-            helicopter_vao_door[i as usize],
-            helicopter_moon.door.index_count,
-        );
-        let mut helicopter_main_rotor_node = SceneNode::from_vao(
-            helicopter_vao_main_rotor[i as usize],
-            helicopter_moon.main_rotor.index_count,
-        );
-        let mut helicopter_tail_rotor_node = SceneNode::from_vao(
-            helicopter_vao_tail_rotor[i as usize],
-            helicopter_moon.tail_rotor.index_count,
-        );
-
-
-        //defining how the helicopter moves (each part moves depending on the other parts)
-        //for example----> the rotors move according to the movements of the main body
- 
-
-
-
-        helicopter_body_node.add_child(&helicopter_door_node);   //moves according to the helicopter body
-        helicopter_body_node.add_child(&helicopter_main_rotor_node);   //moves according to the helicopter body
-        helicopter_body_node.add_child(&helicopter_tail_rotor_node);  //moves according to the helicopter body
-        root_node.add_child(&helicopter_body_node);
-        helicopter_nodes.push(helicopter_body_node);
-        helicopter_main_rotor_node.reference_point = glm::vec3(0.0, 0.0, 0.0); //setting reference points
-        helicopter_tail_rotor_node.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting reference points
-    }
-    root_node.add_child(&terrain_node); 
+        let mut scene = SceneGraph::new();
+        let root_node = scene.root();
+        // Each mesh goes to the GPU once, however many nodes draw it
+        // Meshes with a texture but no coordinates to look it up with are drawn without it
+        let mut textures = TextureCache::new(SamplerSettings::default());
+        let mut meshes = MeshRegistry::new(move |mesh| unsafe {
+            let mut gpu = create_vao(&mesh.vertices, &mesh.indices, &mesh.colors, &mesh.normals, &mesh.tangents, &mesh.uvs);
+            gpu.texture = mesh.material.diffuse_texture.as_ref()
+                .filter(|_| !mesh.uvs.is_empty())
+                .and_then(|path| textures.load(path).map_err(|e| println!("{}", e)).ok());
+            gpu
+        });
+        let scene_file = scene_file::SceneFile::load(".\\resources\\moon.json").expect("Failed to load scene file");
+        let loaded_scene = scene_file.instantiate(&mut scene, root_node, &mut meshes).expect("Failed to build scene");
 
         let mut _arbitrary_number = 0.0;
 
-        let first_frame_time = std::time::Instant::now();
-        let mut last_frame_time = first_frame_time;
-
         let persp_mat: glm::Mat4 =
             glm::perspective((SCREEN_H as f32) / (SCREEN_W as f32), 90.0, 1.0, 1000.0);
 
         // let persp_trans: glm::Mat4 = glm::translation(&glm::vec3(0.0, 0.0, -2.0));
 
-        let mut projection: glm::Mat4 = persp_mat;
-
-        let model: glm::Mat4 = glm::identity();
+        let projection: glm::Mat4 = persp_mat;
 
 
 
@@ -565,15 +635,24 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
         let mut trans_x = 0.0;
         let mut trans_y = 0.0;
         let mut trans_z = -4.0;
-        let rotation: f32 = 1.0;
-        let factor: f32 = 0.2;
         let first_frame_time = std::time::Instant::now();
         let mut prevous_frame_time = first_frame_time;
+        let mut cull_stats = CullStats::default();
+        let mut layer_mask = LAYER_ALL;
+        let mut previous_keys: Vec<VirtualKeyCode> = Vec::new();
         let mut point_of_view: glm::Mat4 = glm::identity();
-        let mut x_factor =30.0;
-        let mut y_factor = 30.0;
-        let mut z_factor = 10.0;
+        let x_factor = 30.0;
+        let y_factor = 30.0;
+        let z_factor = 10.0;
         loop {
+            // Handle resize events
+            if let Ok(mut new_size) = window_size.lock() {
+                if new_size.2 {
+                    context.resize(glutin::dpi::PhysicalSize::new(new_size.0, new_size.1));
+                    new_size.2 = false;
+                    unsafe { gl::Viewport(0, 0, new_size.0 as i32, new_size.1 as i32); }
+                }
+            }
 
             let now = std::time::Instant::now();
             let elapsed = now.duration_since(first_frame_time).as_secs_f32();
@@ -619,6 +698,36 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
                         _ => {}
                     }
                 }
+
+                // F1 and F2 hide and show the terrain and vehicle layers, F3 prints the scene graph and
+                // how many meshes it has on the GPU, F4 writes it out for Graphviz, F5 bakes it into an
+                // OBJ file, F6 prints how many nodes the last frame drew and culled and F7 saves the
+                // scene back out as a scene file. Once per key press.
+                for key in keys.iter().filter(|key| !previous_keys.contains(key)) {
+                    match key {
+                        VirtualKeyCode::F1 => layer_mask ^= LAYER_TERRAIN,
+                        VirtualKeyCode::F2 => layer_mask ^= LAYER_VEHICLES,
+                        VirtualKeyCode::F3 => {
+                            scene.print_tree(root_node);
+                            println!("{} meshes uploaded", meshes.len());
+                        },
+                        VirtualKeyCode::F4 => match std::fs::write("scene.dot", scene.to_dot(root_node)) {
+                            Ok(()) => println!("Wrote the scene graph to scene.dot"),
+                            Err(e) => println!("Failed to write scene.dot: {}", e),
+                        },
+                        VirtualKeyCode::F5 => match obj_export::save_scene_obj(&scene, root_node, layer_mask, "scene.obj") {
+                            Ok(()) => println!("Wrote the scene as drawn to scene.obj"),
+                            Err(e) => println!("{}", e),
+                        },
+                        VirtualKeyCode::F6 => println!("Drew {} nodes, culled {}", cull_stats.drawn, cull_stats.culled),
+                        VirtualKeyCode::F7 => match scene_file::SceneFile::capture(&scene, root_node, &loaded_scene).save("scene.json") {
+                            Ok(()) => println!("Saved the scene to scene.json"),
+                            Err(e) => println!("{}", e),
+                        },
+                        _ => {}
+                    }
+                }
+                previous_keys = keys.clone();
             }
 
             // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
@@ -629,7 +738,6 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
             
     
             //task4
-            let heading = toolbox::simple_heading_animation(elapsed);
  
  
  
@@ -652,20 +760,7 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
 
 
 
-    let mut variance_y = 1.0;  //variable to make the helicopters appear at different heights
-    let mut variance_x = 2.5;  //variable to make the helicopters appear at different points in the x axis
-    for i in 0..total_helicopters {
-        let mut helicopter = &mut helicopter_nodes[i as usize];
-        helicopter.position = glm::vec3(heading.x + (variance_x as f32) * 25.0, 30.0+ (variance_y as f32), heading.z- 80.0);
-        helicopter.rotation = glm::vec3(heading.roll, heading.yaw, heading.pitch);
-        let mut tail_rotor = helicopter.get_child(2); // tail rotor is last one to be pushed
-        tail_rotor.rotation.x = 7.0 * elapsed;
-        let mut main_rotor = helicopter.get_child(1);
-        main_rotor.rotation.y = 5.0 * elapsed;
-        variance_y = variance_y + 25.0; //increase the height y-axis for the next helicopter in the list
-        variance_x = variance_x +1.25; //increase the x axis
-        
-    }
+    animation::animate(&mut scene, elapsed);
 
 
     unsafe {
@@ -714,7 +809,6 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
             * glm::rotation(rotation_y.to_radians(), &glm::vec3(0.0, 1.0, 0.0));
 
         point_of_view = rot * trans * point_of_view;
-        let mut mod_view = point_of_view * model;
         let view_proj_mat = projection * point_of_view;
 
         // set values to zero
@@ -724,8 +818,46 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
         trans_y = 0.0;
         trans_z = 0.0;
 
-        let mut trans: glm::Mat4 = glm::identity();
-        draw_scene(&mut root_node, &view_proj_mat, &glm::identity(), MVP, modelMatrix);
+        // The camera sits at the origin of the view space
+        let camera_position = glm::inverse(&point_of_view).column(3).xyz();
+        for (shader, locations) in [(&textured_shader, &textured_locations), (&simple_shader, &simple_locations)].iter() {
+            shader.activate();
+            gl::Uniform3fv(locations.camera_position, 1, camera_position.as_ptr());
+        }
+
+        scene.update_transforms();
+        let mut context = DrawContext {
+            view_projection_matrix: view_proj_mat,
+            frustum: Frustum::from_matrix(&view_proj_mat),
+            simple: simple_locations,
+            textured: textured_locations,
+            active_program: simple_shader.program_id,
+            layer_mask,
+            stats: CullStats::default(),
+        };
+        draw_scene(&scene, root_node, &mut context, false);
+        cull_stats = context.stats;
+
+        // Report what is under the cursor when the left mouse button is clicked, and stop or restart
+        // the helicopter it belongs to
+        if let Some((x, y)) = pick_request.lock().ok().and_then(|mut request| request.take()) {
+            let (width, height, _) = *window_size.lock().unwrap();
+            let ray = Ray::from_screen(x, y, width as f32, height as f32, &view_proj_mat);
+            match scene.raycast(&ray, root_node, layer_mask) {
+                Some(hit) => {
+                    println!(
+                        "Picked {} (triangle {}) at [{:.2}, {:.2}, {:.2}], facing [{:.2}, {:.2}, {:.2}]",
+                        scene.path_of(hit.node), hit.triangle,
+                        hit.position.x, hit.position.y, hit.position.z,
+                        hit.normal.x, hit.normal.y, hit.normal.z,
+                    );
+                    if let Some(flying) = animation::flying_ancestor(&scene, hit.node) {
+                        animation::toggle_hovering(&mut scene, flying, elapsed);
+                    }
+                },
+                None => println!("Picked nothing"),
+            }
+        }
     }
     
             context.swap_buffers().unwrap();
@@ -739,7 +871,7 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
     thread::spawn(move || {
-        if !render_thread.join().is_ok() {
+        if render_thread.join().is_err() {
             if let Ok(mut health) = render_thread_watchdog.write() {
                 println!("Render thread panicked!");
                 *health = false;
@@ -753,7 +885,7 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
 
         // Terminate program if render thread panics
         if let Ok(health) = render_thread_healthy.read() {
-            if *health == false {
+            if !*health {
                 *control_flow = ControlFlow::Exit;
             }
         }
@@ -796,11 +928,30 @@ helicopter_tail_rotor.reference_point = glm::vec3(0.35, 2.3, 10.4); //setting re
                 }
 
                 // Handle escape separately
-                match keycode {
-                    Escape => {
-                        *control_flow = ControlFlow::Exit;
-                    }
-                    _ => {}
+                if keycode == Escape {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Resized(physical_size),
+                ..
+            } => {
+                if let Ok(mut new_size) = arc_window_size.lock() {
+                    *new_size = (physical_size.width, physical_size.height, true);
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => {
+                cursor_position = (position.x as f32, position.y as f32);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state: Pressed, button: MouseButton::Left, .. },
+                ..
+            } => {
+                if let Ok(mut request) = arc_pick_request.lock() {
+                    *request = Some(cursor_position);
                 }
             }
             Event::DeviceEvent {
@@ -1,124 +1,305 @@
-use tobj;
+use std::path::Path;
+
+use crate::bounds::Bounds;
 
 // internal helper
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num*4).collect()
 }
 
+// Material
+
+// How a surface reflects light, as read from an MTL file. The vertex colours are multiplied by the
+// diffuse colour and the opacity when drawing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub name             : String,
+    pub diffuse          : [f32; 3],
+    pub specular         : [f32; 3],
+    pub shininess        : f32,             // Specular exponent
+    pub opacity          : f32,             // 1 is opaque
+    pub diffuse_texture  : Option<String>,  // Paths of the texture files
+    pub specular_texture : Option<String>,
+    pub normal_texture   : Option<String>,
+}
+
+impl Default for Material {
+    // Plain white and not shiny, which leaves the vertex colours as they are
+    fn default() -> Self {
+        Material {
+            name             : String::from("default"),
+            diffuse          : [1.0, 1.0, 1.0],
+            specular         : [0.0, 0.0, 0.0],
+            shininess        : 32.0,
+            opacity          : 1.0,
+            diffuse_texture  : None,
+            specular_texture : None,
+            normal_texture   : None,
+        }
+    }
+}
+
+impl From<&tobj::Material> for Material {
+    // tobj gives a material without a Kd line a black diffuse colour, which would blot out its
+    // diffuse texture. A black Kd alongside a map_Kd is taken to mean the texture as it is.
+    fn from(material: &tobj::Material) -> Self {
+        let texture = |name: &str| if name.is_empty() { None } else { Some(name.to_string()) };
+        let diffuse_texture = texture(&material.diffuse_texture);
+        let diffuse = if material.diffuse == [0.0; 3] && diffuse_texture.is_some() {
+            [1.0, 1.0, 1.0]
+        } else {
+            material.diffuse
+        };
+        Material {
+            name             : material.name.clone(),
+            diffuse,
+            specular         : material.specular,
+            shininess        : material.shininess,
+            opacity          : material.dissolve,
+            diffuse_texture,
+            specular_texture : texture(&material.specular_texture),
+            normal_texture   : texture(&material.normal_texture),
+        }
+    }
+}
+
 // Mesh
 
+#[derive(Clone)]
 pub struct Mesh {
     pub vertices    : Vec<f32>,
     pub normals     : Vec<f32>,
     pub colors      : Vec<f32>,
     pub indices     : Vec<u32>,
+    pub uvs         : Vec<f32>,     // Texture coordinates, two per vertex, or none at all
+    pub tangents    : Vec<f32>,     // Four per vertex, the last being the sign of the bitangent
+    #[allow(dead_code)]
     pub index_count : i32,
+    pub material    : Material,
 }
 
 impl Mesh {
+    // No vertices, in the default material
+    pub fn empty() -> Self {
+        Mesh {
+            vertices: vec![], normals: vec![], colors: vec![], indices: vec![], uvs: vec![], tangents: vec![],
+            index_count: 0,
+            material: Material::default(),
+        }
+    }
+
+    // Normals are generated if the file has none, and tangents if it has texture coordinates
     pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
         let num_verts = mesh.positions.len() / 3;
         let index_count = mesh.indices.len() as i32;
-        Mesh {
+        let uvs = if mesh.texcoords.len() == num_verts * 2 { mesh.texcoords } else { vec![] };
+        let mut result = Mesh {
             vertices: mesh.positions,
             normals: mesh.normals,
             indices: mesh.indices,
             colors: generate_color_vec(color, num_verts),
+            uvs,
+            tangents: vec![],
             index_count,
-        }
+            material: Material::default(),
+        };
+        result.ensure_normals();
+        result.ensure_tangents();
+        result
     }
-}
 
-// Lunar terrain
-
-pub struct Terrain;
-impl Terrain {
-    pub fn load(path: &str) -> Mesh {
-        println!("Loading terrain model...");
-        let before = std::time::Instant::now();
-        let (models, _materials)
-            = tobj::load_obj(path,
-                &tobj::LoadOptions{
-                    triangulate: true,
-                    single_index: true,
-                    ..Default::default()
-                }
-            ).expect("Failed to load terrain model");
-        let after = std::time::Instant::now();
-        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
-
-        if models.len() > 1 || models.len() == 0 {
-            panic!("Please use a model with a single mesh!")
-            // You could try merging the vertices and indices
-            // of the separate meshes into a single mesh.
-            // I'll leave that as an optional exercise. ;)
+    // An object of a loaded OBJ file, in the material it refers to if there is one by that number
+    pub fn from_model(model: &tobj::Model, materials: &[Material], color: [f32; 4]) -> Self {
+        Mesh {
+            material: model.mesh.material_id.and_then(|id| materials.get(id)).cloned().unwrap_or_default(),
+            ..Mesh::from(model.mesh.clone(), color)
         }
+    }
 
-        let terrain = models[0].to_owned();
-        println!("Loaded {} with {} points and {} triangles.",
-            terrain.name,
-            terrain.mesh.positions.len() /3,
-            terrain.mesh.indices.len() / 3,
-        );
+    // Finds the named object in a set of loaded models. Without a name all of them are merged.
+    pub fn from_models(models: &[tobj::Model], materials: &[Material], object: Option<&str>, color: [f32; 4]) -> Result<Self, String> {
+        let convert = |model| Mesh::from_model(model, materials, color);
+        match object {
+            Some(name) => models.iter().find(|m| m.name == name)
+                .map(convert)
+                .ok_or(format!("No object named {} in the model file", name)),
+            None if models.is_empty() => Err(String::from("The model file has no objects")),
+            None => Ok(Mesh::merge(models.iter().map(convert))),
+        }
+    }
 
-        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
+    // All the meshes in one, with the indices of each shifted past the vertices of those before it.
+    // Meshes without normals get generated ones. Texture coordinates and tangents are only kept if
+    // every mesh has them. If the meshes are in different materials, their diffuse colours and
+    // opacities are baked into the vertex colours, the textures are dropped, and the highlights are
+    // those of the first material.
+    pub fn merge<I: IntoIterator<Item = Mesh>>(meshes: I) -> Mesh {
+        let meshes: Vec<Mesh> = meshes.into_iter().collect();
+        let mut merged = Mesh::empty();
+        let mixed = meshes.windows(2).any(|pair| pair[0].material != pair[1].material);
+        if let Some(first) = meshes.first() {
+            merged.material = first.material.clone();
+            if mixed {
+                merged.material = Material {
+                    name             : String::from("merged"),
+                    specular         : first.material.specular,
+                    shininess        : first.material.shininess,
+                    ..Material::default()
+                };
+            }
+        }
+        let mut all_uvs = true;
+        let mut all_tangents = true;
+        for mesh in meshes {
+            let mut mesh = if mixed { mesh.with_material_baked() } else { mesh };
+            mesh.ensure_normals();
+            let offset = (merged.vertices.len() / 3) as u32;
+            all_uvs &= mesh.uvs.len() / 2 == mesh.vertices.len() / 3;
+            all_tangents &= mesh.tangents.len() / 4 == mesh.vertices.len() / 3;
+            merged.indices.extend(mesh.indices.iter().map(|i| i + offset));
+            merged.vertices.extend(mesh.vertices);
+            merged.normals.extend(mesh.normals);
+            merged.colors.extend(mesh.colors);
+            merged.uvs.extend(mesh.uvs);
+            merged.tangents.extend(mesh.tangents);
+        }
+        if !all_uvs {
+            merged.uvs.clear();
+        }
+        if !all_uvs || !all_tangents {
+            merged.tangents.clear();
+        }
+        merged.index_count = merged.indices.len() as i32;
+        merged
     }
-}
 
+    // Appends a copy of the vertex, with everything every vertex has, and returns the copy's index
+    pub(crate) fn duplicate_vertex(&mut self, vertex: u32) -> u32 {
+        let i = vertex as usize;
+        let count = self.vertices.len() / 3;
+        fn copy(attribute: &mut Vec<f32>, size: usize, count: usize, i: usize) {
+            if attribute.len() == size * count {
+                attribute.extend_from_within(size * i..size * i + size);
+            }
+        }
+        copy(&mut self.normals, 3, count, i);
+        copy(&mut self.colors, 4, count, i);
+        copy(&mut self.uvs, 2, count, i);
+        copy(&mut self.tangents, 4, count, i);
+        copy(&mut self.vertices, 3, count, i);
+        count as u32
+    }
 
-// Helicopter
+    // The extent of the mesh, or None if it has no vertices
+    pub fn bounds(&self) -> Option<Bounds> {
+        Bounds::from_points(&self.vertices)
+    }
 
-pub struct Helicopter {
-    pub body       : Mesh,
-    pub door       : Mesh,
-    pub main_rotor : Mesh,
-    pub tail_rotor : Mesh,
-}
+    // The mesh with its diffuse colour and opacity multiplied into the vertex colours
+    fn with_material_baked(self) -> Self {
+        let [r, g, b] = self.material.diffuse;
+        let color = [r, g, b, self.material.opacity];
+        Mesh {
+            material: Material { diffuse: [1.0, 1.0, 1.0], opacity: 1.0, ..self.material.clone() },
+            ..self.tinted(color)
+        }
+    }
 
-// You can use square brackets to access the components of the helicopter, if you want to use loops!
-use std::ops::Index;
-impl Index<usize> for Helicopter {
-    type Output = Mesh;
-    fn index<'a>(&'a self, i: usize) -> &'a Mesh {
-        match i {
-            0 => &self.body,
-            1 => &self.main_rotor,
-            2 => &self.tail_rotor,
-            3 => &self.door,
-            _ => panic!("Invalid index, try [0,3]"),
+    // A copy of the mesh with every vertex colour multiplied by `color`
+    pub fn tinted(&self, color: [f32; 4]) -> Self {
+        Mesh {
+            colors: self.colors.chunks_exact(4)
+                .flat_map(|c| (0..4).map(move |i| c[i] * color[i]))
+                .collect(),
+            ..self.clone()
         }
     }
 }
 
-impl Helicopter {
-    pub fn load(path: &str) -> Self {
-        println!("Loading helicopter model...");
-        let before = std::time::Instant::now();
-        let (models, _materials)
-            = tobj::load_obj(path,
-                &tobj::LoadOptions{
-                    triangulate: true,
-                    single_index: true,
-                    ..Default::default()
-                }
-            ).expect("Failed to load helicopter model");
-        let after = std::time::Instant::now();
-        println!("Done in {:.3}ms!", after.duration_since(before).as_micros() as f32 / 1e3);
-
-        for model in &models {
-            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+// Reads every object out of an OBJ file, triangulated and with a single index buffer, along with
+// the materials of its MTL file. A missing or broken MTL file only costs the materials. The paths
+// of the textures are taken to be relative to the OBJ file.
+pub fn load_obj_models(path: &str) -> Result<(Vec<tobj::Model>, Vec<Material>), String> {
+    let (models, materials) = tobj::load_obj(path,
+        &tobj::LoadOptions{
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
         }
+    )
+    .map_err(|e| format!("Failed to load {}: {}", path, e))?;
+    let materials = match materials {
+        Ok(materials) => {
+            let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+            let resolve = |texture: Option<String>| texture.map(|name| directory.join(name).to_string_lossy().into_owned());
+            materials.iter()
+                .map(Material::from)
+                .map(|material| Material {
+                    diffuse_texture  : resolve(material.diffuse_texture.clone()),
+                    specular_texture : resolve(material.specular_texture.clone()),
+                    normal_texture   : resolve(material.normal_texture.clone()),
+                    ..material
+                })
+                .collect()
+        },
+        Err(e) => {
+            if models.iter().any(|model| model.mesh.material_id.is_some()) {
+                println!("No materials for {}: {}", path, e);
+            }
+            vec![]
+        },
+    };
+    Ok((models, materials))
+}
 
-        let body_model = models.iter().find(|m| m.name == "Body_body").expect("Incorrect model file!").to_owned();
-        let door_model = models.iter().find(|m| m.name == "Door_door").expect("Incorrect model file!").to_owned();
-        let main_rotor_model = models.iter().find(|m| m.name == "Main_Rotor_main_rotor").expect("Incorrect model file!").to_owned();
-        let tail_rotor_model = models.iter().find(|m| m.name == "Tail_Rotor_tail_rotor").expect("Incorrect model file!").to_owned();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Helicopter {
-            body:       Mesh::from(body_model.mesh,         [0.3, 0.3, 0.3, 1.0]),
-            door:       Mesh::from(door_model.mesh,         [0.1, 0.1, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh,   [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh,   [0.1, 0.3, 0.1, 1.0]),
+    #[test]
+    fn black_diffuse_with_a_texture_is_white() {
+        let textured = tobj::Material { diffuse_texture: String::from("albedo.png"), ..tobj::Material::default() };
+        assert_eq!(Material::from(&textured).diffuse, [1.0, 1.0, 1.0]);
+
+        let tinted = tobj::Material { diffuse: [0.5, 0.2, 0.1], ..textured };
+        assert_eq!(Material::from(&tinted).diffuse, [0.5, 0.2, 0.1]);
+
+        let black = tobj::Material::default();
+        assert_eq!(Material::from(&black).diffuse, [0.0, 0.0, 0.0]);
+    }
+
+    // A triangle in the XY plane, moved along X
+    fn triangle(x: f32, with_uvs: bool) -> Mesh {
+        let mut mesh = Mesh::empty();
+        mesh.vertices = vec![x, 0.0, 0.0, x + 1.0, 0.0, 0.0, x, 1.0, 0.0];
+        mesh.colors = vec![1.0; 12];
+        mesh.indices = vec![0, 1, 2];
+        mesh.index_count = 3;
+        if with_uvs {
+            mesh.uvs = vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0];
         }
+        mesh
+    }
+
+    #[test]
+    fn merged_meshes_shift_the_indices_past_the_vertices_before_them() {
+        let merged = Mesh::merge(vec![triangle(0.0, true), triangle(5.0, true), triangle(10.0, true)]);
+        assert_eq!(merged.indices, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(merged.index_count, 9);
+        assert_eq!(merged.vertices.len(), 27);
+        assert_eq!(&merged.vertices[9..12], &[5.0, 0.0, 0.0]);
+        // Generated where missing, and facing +Z for these triangles
+        assert_eq!(merged.normals.len(), 27);
+        assert!(merged.normals.chunks_exact(3).all(|n| (n[2] - 1.0).abs() < 1e-6));
+        assert_eq!(merged.colors.len(), 36);
+        assert_eq!(merged.uvs.len(), 18);
+    }
+
+    #[test]
+    fn texture_coordinates_only_survive_a_merge_if_every_mesh_has_them() {
+        let merged = Mesh::merge(vec![triangle(0.0, true), triangle(5.0, false)]);
+        assert_eq!(merged.indices, [0, 1, 2, 3, 4, 5]);
+        assert!(merged.uvs.is_empty() && merged.tangents.is_empty());
+        assert!(Mesh::merge(vec![]).vertices.is_empty());
     }
 }
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+
+use crate::bounds::Bounds;
+use crate::gltf_import::{self, GltfScene};
+use crate::heightmap::{self, HeightmapOptions};
+use crate::mesh::{self, Material, Mesh};
+use crate::texture::Texture2D;
+use crate::{ply, stl};
+
+// The OpenGL objects a mesh was uploaded to. They are deleted along with this, and the texture
+// along with the last mesh using it.
+pub struct GpuMesh {
+    pub vao_id      : u32,
+    pub index_count : i32,
+    pub texture     : Option<Rc<Texture2D>>,    // The material's diffuse texture, if it has one
+    buffers         : Vec<u32>,                 // The VBOs and the index buffer the VAO refers to
+}
+
+impl GpuMesh {
+    pub fn new(vao_id: u32, index_count: i32, buffers: Vec<u32>) -> GpuMesh {
+        GpuMesh { vao_id, index_count, texture: None, buffers }
+    }
+}
+
+impl Drop for GpuMesh {
+    fn drop(&mut self) {
+        // glGenVertexArrays never hands out 0, so a 0 here means nothing was uploaded
+        if self.vao_id != 0 {
+            unsafe {
+                gl::DeleteVertexArrays(1, &self.vao_id);
+                gl::DeleteBuffers(self.buffers.len() as i32, self.buffers.as_ptr());
+            }
+        }
+    }
+}
+
+// A mesh as it is shared between scene nodes: on the GPU for drawing, and on the CPU for picking
+// and anything else that needs to look at the triangles
+pub struct MeshResource {
+    pub name   : String,            // What the registry knows it as
+    pub mesh   : Mesh,
+    pub bounds : Option<Bounds>,    // The extent of the mesh, in its own coordinate system
+    pub gpu    : GpuMesh,
+}
+
+// Every node drawing the mesh holds one of these. The mesh is freed, GPU side included, once the
+// last handle to it is dropped.
+pub type MeshHandle = Rc<MeshResource>;
+
+// Hands out shared handles to meshes, uploading each of them only once. The registry does not keep
+// meshes alive on its own, so a mesh nobody references any more gets uploaded again if it is
+// asked for later.
+pub struct MeshRegistry {
+    entries : HashMap<String, Weak<MeshResource>>,
+    upload  : Box<dyn FnMut(&Mesh) -> GpuMesh>,
+}
+
+impl MeshRegistry {
+
+    // `upload` copies a mesh to the GPU. It is only called from the registry's methods, so with
+    // OpenGL that means from the thread owning the context.
+    pub fn new<F>(upload: F) -> MeshRegistry
+    where F: FnMut(&Mesh) -> GpuMesh + 'static
+    {
+        MeshRegistry { entries: HashMap::new(), upload: Box::new(upload) }
+    }
+
+    // The mesh registered under `name`, if something still uses it
+    pub fn get(&self, name: &str) -> Option<MeshHandle> {
+        self.entries.get(name).and_then(Weak::upgrade)
+    }
+
+    // The mesh registered under `name`. If there is none, `make` is asked for it and the result is
+    // uploaded and registered.
+    pub fn load<F>(&mut self, name: &str, make: F) -> Result<MeshHandle, String>
+    where F: FnOnce() -> Result<Mesh, String>
+    {
+        if let Some(handle) = self.get(name) {
+            return Ok(handle);
+        }
+        self.purge();
+        let mesh = make()?;
+        let resource = Rc::new(MeshResource {
+            name   : name.to_string(),
+            bounds : mesh.bounds(),
+            gpu    : (self.upload)(&mesh),
+            mesh,
+        });
+        self.entries.insert(name.to_string(), Rc::downgrade(&resource));
+        Ok(resource)
+    }
+
+    // Forgets the meshes that have been freed
+    pub fn purge(&mut self) {
+        self.entries.retain(|_, resource| resource.strong_count() > 0);
+    }
+
+    // Number of meshes currently alive
+    pub fn len(&self) -> usize {
+        self.entries.values().filter(|resource| resource.strong_count() > 0).count()
+    }
+
+    // A registry that never touches OpenGL, for tests running without a context
+    #[cfg(test)]
+    pub fn without_gpu() -> MeshRegistry {
+        MeshRegistry::new(|_| GpuMesh::new(0, 0, vec![]))
+    }
+
+}
+
+// What the registry calls an object of a mesh file in a colour, or the whole file without an object
+pub fn resource_name(path: &Path, object: Option<&str>, color: [f32; 4]) -> String {
+    format!("{}#{} {:?}", path.display(), object.unwrap_or(""), color)
+}
+
+// Mesh files that have been parsed, kept around while a scene is being built so that files with
+// several objects in them are only read once
+#[derive(Default)]
+pub struct MeshFiles {
+    obj  : HashMap<PathBuf, (Vec<tobj::Model>, Vec<Material>)>,
+    gltf : HashMap<PathBuf, GltfScene>,
+}
+
+impl MeshFiles {
+
+    // An object of an OBJ or glTF file, or the whole file, in the given colour. The file type goes
+    // by the extension: .gltf and .glb, .ply, .stl, and OBJ for anything else. OBJ meshes are
+    // painted in the colour and keep the materials of their MTL file, the others keep their own
+    // colours tinted by it. Comes from the registry if it has the mesh already, and is named after
+    // where it came from, so that the same object in the same colour is shared by everyone using it.
+    pub fn load(&mut self, registry: &mut MeshRegistry, path: &Path, object: Option<&str>, color: [f32; 4]) -> Result<MeshHandle, String> {
+        let name = resource_name(path, object, color);
+        registry.load(&name, || {
+            let file = path.to_string_lossy();
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("gltf") | Some("glb") => self.gltf(path)?.mesh(object).map(|mesh| mesh.tinted(color)),
+                Some("ply") => ply::load_ply(&file).map(|mesh| mesh.tinted(color)),
+                Some("stl") => stl::load_stl(&file).map(|mesh| mesh.tinted(color)),
+                _ => {
+                    if !self.obj.contains_key(path) {
+                        self.obj.insert(path.to_path_buf(), mesh::load_obj_models(&file)?);
+                    }
+                    let (models, materials) = &self.obj[path];
+                    Mesh::from_models(models, materials, object, color)
+                        .map_err(|e| format!("{}: {}", file, e))
+                },
+            }
+        })
+    }
+
+    // The whole of a glTF file, read the first time it is asked for
+    pub fn gltf(&mut self, path: &Path) -> Result<&GltfScene, String> {
+        if !self.gltf.contains_key(path) {
+            self.gltf.insert(path.to_path_buf(), gltf_import::load_gltf(&path.to_string_lossy())?);
+        }
+        Ok(&self.gltf[path])
+    }
+
+    // Terrain made from a heightmap image, tinted in the given colour. Shared like the meshes from
+    // other files, by everyone using the same image with the same options.
+    pub fn load_heightmap(&mut self, registry: &mut MeshRegistry, path: &Path, options: &HeightmapOptions, color: [f32; 4]) -> Result<MeshHandle, String> {
+        let name = format!("{}#heightmap {:?} {:?}", path.display(), options, color);
+        registry.load(&name, || heightmap::load_heightmap(&path.to_string_lossy(), options).map(|mesh| mesh.tinted(color)))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn triangle() -> Result<Mesh, String> {
+        let mut mesh = Mesh::empty();
+        mesh.vertices = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        mesh.indices = vec![0, 1, 2];
+        mesh.index_count = 3;
+        Ok(mesh)
+    }
+
+    #[test]
+    fn meshes_are_shared_while_in_use_and_made_again_afterwards() {
+        let mut registry = MeshRegistry::without_gpu();
+        let made = Cell::new(0);
+        let make = || {
+            made.set(made.get() + 1);
+            triangle()
+        };
+
+        let first = registry.load("triangle", make).unwrap();
+        let second = registry.load("triangle", make).unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(made.get(), 1);
+        assert_eq!(registry.len(), 1);
+        assert_eq!(first.name, "triangle");
+        assert!(first.bounds.is_some());
+
+        drop(first);
+        assert!(registry.get("triangle").is_some());
+        drop(second);
+        assert!(registry.get("triangle").is_none());
+        assert_eq!(registry.len(), 0);
+
+        let again = registry.load("triangle", make).unwrap();
+        assert_eq!(made.get(), 2);
+        assert_eq!(registry.len(), 1);
+        assert!(Rc::ptr_eq(&again, &registry.get("triangle").unwrap()));
+    }
+
+    #[test]
+    fn failing_meshes_are_not_registered() {
+        let mut registry = MeshRegistry::without_gpu();
+        assert!(registry.load("broken", || Err("No such file".to_string())).is_err());
+        assert!(registry.get("broken").is_none());
+        assert_eq!(registry.len(), 0);
+    }
+}
@@ -0,0 +1,183 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use crate::mesh::Mesh;
+
+// Vertex normals for meshes that come without them. Every corner of a triangle is smoothed with the
+// triangles around the same point that meet it at no more than the crease angle, and a vertex whose
+// corners end up facing different ways is split into one vertex per way. A crease angle of 0 gives
+// flat shading, and one of PI smooths over every edge.
+
+// What meshes loaded without normals get: round surfaces come out smooth, boxes keep their edges
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
+// Faces at a smaller angle than this count as coplanar, even with the crease angle at 0
+const COPLANAR_EPSILON: f32 = 1e-4;
+
+impl Mesh {
+
+    // Generates normals with the default crease angle, unless every vertex has one already
+    pub fn ensure_normals(&mut self) {
+        if self.normals.len() != self.vertices.len() {
+            self.generate_normals(DEFAULT_CREASE_ANGLE);
+        }
+    }
+
+    // Replaces the normals, splitting vertices at edges sharper than `crease_angle` (in radians)
+    pub fn generate_normals(&mut self, crease_angle: f32) {
+        let position = |i: u32| glm::make_vec3(&self.vertices[3 * i as usize..3 * i as usize + 3]);
+
+        // Area weighted, so that slivers do not tip the normals over
+        let face_normals: Vec<glm::Vec3> = self.indices.chunks_exact(3)
+            .map(|t| glm::cross(&(position(t[1]) - position(t[0])), &(position(t[2]) - position(t[0]))))
+            .collect();
+        let directions: Vec<glm::Vec3> = face_normals.iter()
+            .map(|n| if glm::length(n) > 0.0 { glm::normalize(n) } else { glm::zero() })
+            .collect();
+
+        // The corners at each point, going by position so that vertices split for other reasons
+        // (texture seams) are still smoothed together
+        let mut corners_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (corner, &i) in self.indices.iter().enumerate() {
+            let p = position(i);
+            corners_at.entry([p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]).or_default().push(corner);
+        }
+
+        let threshold = crease_angle.cos() - COPLANAR_EPSILON;
+        let mut corner_normals = vec![glm::Vec3::zeros(); self.indices.len()];
+        for corners in corners_at.values() {
+            for &corner in corners {
+                let own = directions[corner / 3];
+                let sum = corners.iter()
+                    .map(|&other| other / 3)
+                    .filter(|&triangle| triangle == corner / 3 || glm::dot(&own, &directions[triangle]) >= threshold)
+                    .fold(glm::Vec3::zeros(), |sum, triangle| sum + face_normals[triangle]);
+                corner_normals[corner] = if glm::length(&sum) > 0.0 { glm::normalize(&sum) } else { glm::vec3(0.0, 1.0, 0.0) };
+            }
+        }
+
+        // Corners of a vertex facing the same way share it, the others get copies
+        let mut normals = vec![None; self.vertices.len() / 3];
+        let mut copies: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+        for (corner, normal) in corner_normals.iter().enumerate() {
+            let vertex = self.indices[corner];
+            let key = (vertex, [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()]);
+            match normals[vertex as usize] {
+                None => normals[vertex as usize] = Some(*normal),
+                Some(existing) if existing == *normal => {},
+                Some(_) => {
+                    let copy = match copies.get(&key) {
+                        Some(&copy) => copy,
+                        None => {
+                            let copy = self.duplicate_vertex(vertex);
+                            normals.push(Some(*normal));
+                            copies.insert(key, copy);
+                            copy
+                        },
+                    };
+                    self.indices[corner] = copy;
+                },
+            }
+        }
+
+        // Vertices no triangle uses face up
+        self.normals = normals.into_iter()
+            .flat_map(|normal| {
+                let n = normal.unwrap_or_else(|| glm::vec3(0.0, 1.0, 0.0));
+                [n.x, n.y, n.z]
+            })
+            .collect();
+        self.index_count = self.indices.len() as i32;
+
+        // Tangents lie flat on the surface, so the ones there were need making again
+        if !self.tangents.is_empty() {
+            self.generate_tangents();
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh(vertices: Vec<f32>, indices: Vec<u32>) -> Mesh {
+        let mut mesh = Mesh::empty();
+        mesh.colors = vec![1.0; vertices.len() / 3 * 4];
+        mesh.index_count = indices.len() as i32;
+        mesh.vertices = vertices;
+        mesh.indices = indices;
+        mesh
+    }
+
+    fn normal(mesh: &Mesh, vertex: u32) -> glm::Vec3 {
+        glm::make_vec3(&mesh.normals[3 * vertex as usize..3 * vertex as usize + 3])
+    }
+
+    fn position(mesh: &Mesh, vertex: u32) -> glm::Vec3 {
+        glm::make_vec3(&mesh.vertices[3 * vertex as usize..3 * vertex as usize + 3])
+    }
+
+    // The eight corners of a unit cube, shared by its twelve triangles, wound counterclockwise from outside
+    fn cube() -> Mesh {
+        let vertices = (0..8)
+            .flat_map(|i| vec![(i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32])
+            .collect();
+        let quads = [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]];
+        let indices = quads.iter().flat_map(|q| vec![q[0], q[1], q[2], q[0], q[2], q[3]]).collect();
+        mesh(vertices, indices)
+    }
+
+    #[test]
+    fn cubes_split_into_a_vertex_per_face_and_corner() {
+        let mut cube = cube();
+        cube.ensure_normals();
+        assert_eq!(cube.vertices.len() / 3, 24);
+        assert_eq!(cube.normals.len(), cube.vertices.len());
+        assert_eq!(cube.colors.len(), 24 * 4);
+        assert_eq!(cube.index_count, 36);
+        let center = glm::vec3(0.5, 0.5, 0.5);
+        for triangle in cube.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+            let face = glm::normalize(&glm::cross(&(position(&cube, b) - position(&cube, a)), &(position(&cube, c) - position(&cube, a))));
+            // Flat, and pointing out of the cube
+            assert!(glm::dot(&face, &(position(&cube, a) - center)) > 0.0);
+            for &vertex in triangle {
+                assert!(glm::distance(&normal(&cube, vertex), &face) < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn shallow_folds_stay_smooth() {
+        // Two quads along X, the second tipped down by ten degrees about the shared edge at x = 1
+        let (sin, cos) = 10f32.to_radians().sin_cos();
+        let mut strip = mesh(
+            vec![0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0 + cos, -sin, 0.0, 1.0 + cos, -sin, 1.0],
+            vec![0, 1, 2, 2, 1, 3, 2, 3, 4, 4, 3, 5],
+        );
+        strip.generate_normals(DEFAULT_CREASE_ANGLE);
+        assert_eq!(strip.vertices.len(), 18);
+        assert!(glm::distance(&normal(&strip, 0), &glm::vec3(0.0, 1.0, 0.0)) < 1e-6);
+        assert!(glm::distance(&normal(&strip, 4), &glm::vec3(sin, cos, 0.0)) < 1e-6);
+        // Averaged on the shared edge, weighted by the areas of the triangles meeting there
+        let (flat, tipped) = (glm::vec3(0.0, 1.0, 0.0), glm::vec3(sin, cos, 0.0));
+        assert!(glm::distance(&normal(&strip, 2), &glm::normalize(&(flat * 2.0 + tipped))) < 1e-6);
+        assert!(glm::distance(&normal(&strip, 3), &glm::normalize(&(flat + tipped * 2.0))) < 1e-6);
+
+        // The same fold is sharp enough to keep with a crease angle below it
+        strip.generate_normals(5f32.to_radians());
+        assert_eq!(strip.vertices.len(), 24);
+    }
+
+    #[test]
+    fn meshes_with_normals_keep_them() {
+        let mut cube = cube();
+        cube.normals = [0.0, 0.0, 1.0].repeat(8);
+        let before = (cube.vertices.clone(), cube.indices.clone());
+        cube.ensure_normals();
+        assert_eq!(cube.normals, [0.0, 0.0, 1.0].repeat(8));
+        assert_eq!((cube.vertices, cube.indices), before);
+    }
+}
@@ -0,0 +1,193 @@
+extern crate nalgebra_glm as glm;
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::bounds::transform_point;
+use crate::mesh::{Material, Mesh};
+use crate::scene_graph::{NodeId, SceneGraph};
+
+// Writing meshes out as Wavefront OBJ, with an MTL file beside it for their materials, so they can
+// be looked at in Blender and other modelling tools. The MTL file gets the OBJ file's name with the
+// extension swapped.
+
+// A mesh placed in the file: its object name and where it goes
+struct Placed<'a> {
+    name      : String,
+    mesh      : &'a Mesh,
+    transform : glm::Mat4,
+}
+
+#[allow(dead_code)]
+pub fn save_mesh_obj(mesh: &Mesh, name: &str, path: &str) -> Result<(), String> {
+    save_placed(&[Placed { name: name.to_string(), mesh, transform: glm::identity() }], path)
+}
+
+// Everything draw_scene would draw below `from` in the given layers, baked into world space with
+// one object per node, named after its path in the graph. The transforms are composed from the
+// nodes' fields, so the caches need not be up to date.
+pub fn save_scene_obj(graph: &SceneGraph, from: NodeId, layers: u32, path: &str) -> Result<(), String> {
+    let placed: Vec<Placed> = graph.depth_first(from)
+        .filter(|visit| visit.node.layers & layers != 0 && graph.is_visible_in_hierarchy(visit.id))
+        .filter_map(|visit| visit.node.mesh.as_ref().map(|resource| Placed {
+            name      : graph.path_of(visit.id),
+            mesh      : &resource.mesh,
+            transform : visit.world,
+        }))
+        .collect();
+    save_placed(&placed, path)
+}
+
+fn save_placed(placed: &[Placed], path: &str) -> Result<(), String> {
+    let mtl_path = Path::new(path).with_extension("mtl");
+    let mtl_name = mtl_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut obj = vec![];
+    let mut mtl = vec![];
+    write_obj(placed, &mtl_name, &mut obj, &mut mtl).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    std::fs::write(path, obj).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    std::fs::write(&mtl_path, mtl).map_err(|e| format!("Failed to write {}: {}", mtl_path.display(), e))
+}
+
+fn write_obj<'a, W: Write>(placed: &[Placed<'a>], mtl_name: &str, obj: &mut W, mtl: &mut W) -> std::io::Result<()> {
+    writeln!(obj, "# Written by gloom-rs\nmtllib {}", mtl_name)?;
+    writeln!(mtl, "# Written by gloom-rs")?;
+
+    // OBJ counts vertices from 1, across all objects
+    let mut vertex_offset = 1;
+    let mut normal_offset = 1;
+    let mut uv_offset = 1;
+    let mut materials: Vec<([f32; 4], &'a Material)> = vec![];
+    for item in placed {
+        let mesh = item.mesh;
+        let vertex_count = mesh.vertices.len() / 3;
+        let has_normals = mesh.normals.len() == mesh.vertices.len();
+        let has_uvs = mesh.uvs.len() / 2 == vertex_count && vertex_count > 0;
+        let normal_matrix = glm::transpose(&glm::inverse(&glm::mat4_to_mat3(&item.transform)));
+        // Mirroring turns the triangles inside out, so their winding has to be turned back
+        let mirrored = glm::mat4_to_mat3(&item.transform).determinant() < 0.0;
+
+        // A mesh painted in one colour has it folded into the object's material. Otherwise the
+        // vertices get their colours after their positions, as tools like Blender and MeshLab read
+        // them, with the opacity left to the material if it is the same everywhere.
+        let mut colors = mesh.colors.chunks_exact(4).take(vertex_count);
+        let first = colors.next().map(|c| [c[0], c[1], c[2], c[3]]).unwrap_or([1.0, 1.0, 1.0, 1.0]);
+        let per_vertex = colors.clone().any(|c| c[..3] != first[..3]);
+        let color = if !per_vertex {
+            first
+        } else if colors.all(|c| c[3] == first[3]) {
+            [1.0, 1.0, 1.0, first[3]]
+        } else {
+            [1.0, 1.0, 1.0, 1.0]
+        };
+        let material = match materials.iter().position(|(c, m)| *c == color && *m == &mesh.material) {
+            Some(index) => index,
+            None => {
+                write_material(mtl, materials.len(), color, &mesh.material)?;
+                materials.push((color, &mesh.material));
+                materials.len() - 1
+            },
+        };
+
+        writeln!(obj, "\no {}\nusemtl material_{}", item.name.replace(char::is_whitespace, "_"), material)?;
+        for (i, v) in mesh.vertices.chunks_exact(3).enumerate() {
+            let p = transform_point(&item.transform, &glm::make_vec3(v));
+            match mesh.colors.get(4 * i..4 * i + 3).filter(|_| per_vertex) {
+                Some(c) => writeln!(obj, "v {} {} {} {} {} {}", p.x, p.y, p.z, c[0], c[1], c[2])?,
+                None => writeln!(obj, "v {} {} {}", p.x, p.y, p.z)?,
+            }
+        }
+        if has_uvs {
+            for uv in mesh.uvs.chunks_exact(2) {
+                writeln!(obj, "vt {} {}", uv[0], uv[1])?;
+            }
+        }
+        if has_normals {
+            for n in mesh.normals.chunks_exact(3) {
+                let n = glm::normalize(&(normal_matrix * glm::make_vec3(n)));
+                writeln!(obj, "vn {} {} {}", n.x, n.y, n.z)?;
+            }
+        }
+        for triangle in mesh.indices.chunks_exact(3) {
+            let order = if mirrored { [0, 2, 1] } else { [0, 1, 2] };
+            let corners: Vec<String> = order.iter()
+                .map(|&corner| {
+                    let i = triangle[corner] as usize;
+                    match (has_uvs, has_normals) {
+                        (true, true) => format!("{}/{}/{}", vertex_offset + i, uv_offset + i, normal_offset + i),
+                        (true, false) => format!("{}/{}", vertex_offset + i, uv_offset + i),
+                        (false, true) => format!("{}//{}", vertex_offset + i, normal_offset + i),
+                        (false, false) => format!("{}", vertex_offset + i),
+                    }
+                })
+                .collect();
+            writeln!(obj, "f {}", corners.join(" "))?;
+        }
+        vertex_offset += vertex_count;
+        if has_normals {
+            normal_offset += vertex_count;
+        }
+        if has_uvs {
+            uv_offset += vertex_count;
+        }
+    }
+    Ok(())
+}
+
+fn write_material<W: Write>(mtl: &mut W, index: usize, color: [f32; 4], material: &Material) -> std::io::Result<()> {
+    let diffuse: Vec<f32> = (0..3).map(|i| color[i] * material.diffuse[i]).collect();
+    writeln!(mtl, "\nnewmtl material_{}", index)?;
+    writeln!(mtl, "Kd {} {} {}", diffuse[0], diffuse[1], diffuse[2])?;
+    writeln!(mtl, "Ks {} {} {}", material.specular[0], material.specular[1], material.specular[2])?;
+    writeln!(mtl, "Ns {}", material.shininess)?;
+    writeln!(mtl, "d {}", color[3] * material.opacity)?;
+    let textures = [
+        ("map_Kd", &material.diffuse_texture),
+        ("map_Ks", &material.specular_texture),
+        ("map_Bump", &material.normal_texture),
+    ];
+    for (keyword, texture) in textures {
+        if let Some(texture) = texture {
+            writeln!(mtl, "{} {}", keyword, texture)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle(colors: &[[f32; 4]; 3]) -> Mesh {
+        let mut mesh = Mesh::empty();
+        mesh.vertices = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        mesh.colors = colors.iter().flatten().copied().collect();
+        mesh.indices = vec![0, 1, 2];
+        mesh.index_count = 3;
+        mesh
+    }
+
+    fn export(mesh: &Mesh) -> (String, String) {
+        let placed = [Placed { name: String::from("triangle"), mesh, transform: glm::identity() }];
+        let (mut obj, mut mtl) = (vec![], vec![]);
+        write_obj(&placed, "test.mtl", &mut obj, &mut mtl).unwrap();
+        (String::from_utf8(obj).unwrap(), String::from_utf8(mtl).unwrap())
+    }
+
+    #[test]
+    fn one_colour_goes_into_the_material() {
+        let red = [1.0, 0.0, 0.0, 0.5];
+        let (obj, mtl) = export(&triangle(&[red, red, red]));
+        assert!(obj.contains("v 1 0 0\n"));
+        assert!(mtl.contains("Kd 1 0 0\n"));
+        assert!(mtl.contains("d 0.5\n"));
+    }
+
+    #[test]
+    fn several_colours_go_on_the_vertices() {
+        let (obj, mtl) = export(&triangle(&[[1.0, 0.0, 0.0, 0.5], [0.0, 1.0, 0.0, 0.5], [0.0, 0.0, 1.0, 0.5]]));
+        let vertices: Vec<&str> = obj.lines().filter(|line| line.starts_with("v ")).collect();
+        assert_eq!(vertices, ["v 0 0 0 1 0 0", "v 1 0 0 0 1 0", "v 0 1 0 0 0 1"]);
+        assert!(mtl.contains("Kd 1 1 1\n"));
+        assert!(mtl.contains("d 0.5\n"));
+    }
+}
@@ -0,0 +1,321 @@
+extern crate nalgebra_glm as glm;
+
+use crate::bounds::{transform_point, Aabb};
+use crate::mesh::Mesh;
+use crate::scene_graph::{NodeId, SceneGraph};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin    : glm::Vec3,
+    pub direction : glm::Vec3,  // Normalized
+}
+
+// Where a ray struck the scene
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub node     : NodeId,
+    pub triangle : usize,       // Index of the triangle in the node's mesh, i.e. indices[3*triangle..]
+    pub position : glm::Vec3,   // World space
+    pub normal   : glm::Vec3,   // World space, interpolated from the vertex normals
+    pub distance : f32,         // From the ray origin, in world units
+}
+
+impl Ray {
+
+    pub fn new(origin: glm::Vec3, direction: glm::Vec3) -> Ray {
+        Ray { origin, direction: glm::normalize(&direction) }
+    }
+
+    // The ray through a pixel, starting on the near plane. Pixels are counted from the top left
+    // corner of the window, like the cursor positions reported by glutin.
+    pub fn from_screen(x: f32, y: f32, width: f32, height: f32, view_projection: &glm::Mat4) -> Ray {
+        let ndc_x = 2.0 * x / width - 1.0;
+        let ndc_y = 1.0 - 2.0 * y / height;
+        let inverse = glm::inverse(view_projection);
+        let unproject = |z: f32| {
+            let p = inverse * glm::vec4(ndc_x, ndc_y, z, 1.0);
+            p.xyz() / p.w
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        Ray::new(near, far - near)
+    }
+
+    pub fn at(&self, t: f32) -> glm::Vec3 {
+        self.origin + self.direction * t
+    }
+
+    // The ray in another coordinate system. The direction is left unnormalized, so that distances
+    // along it keep meaning the same thing in both systems.
+    fn transformed(&self, matrix: &glm::Mat4) -> Ray {
+        Ray {
+            origin    : transform_point(matrix, &self.origin),
+            direction : glm::mat4_to_mat3(matrix) * self.direction,
+        }
+    }
+
+    // Distance to where the ray enters the box, or None if it misses (slab test)
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::INFINITY;
+        for i in 0..3 {
+            let inverse = 1.0 / self.direction[i];
+            let t0 = (aabb.min[i] - self.origin[i]) * inverse;
+            let t1 = (aabb.max[i] - self.origin[i]) * inverse;
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+        if t_min <= t_max { Some(t_min) } else { None }
+    }
+
+    // Möller-Trumbore, hitting the triangle from either side. Returns the distance along the ray
+    // and the barycentric coordinates of b and c.
+    pub fn intersect_triangle(&self, a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3) -> Option<(f32, f32, f32)> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = glm::cross(&self.direction, &edge2);
+        let determinant = glm::dot(&edge1, &p);
+        if determinant.abs() < 1e-12 {
+            return None; // Parallel to the triangle
+        }
+        let inverse = 1.0 / determinant;
+        let to_origin = self.origin - a;
+        let u = glm::dot(&to_origin, &p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = glm::cross(&to_origin, &edge1);
+        let v = glm::dot(&self.direction, &q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = glm::dot(&edge2, &q) * inverse;
+        if t < 0.0 { None } else { Some((t, u, v)) }
+    }
+
+    // The nearest triangle of the mesh the ray hits, as (triangle, t, u, v)
+    pub fn intersect_mesh(&self, mesh: &Mesh) -> Option<(usize, f32, f32, f32)> {
+        let vertex = |i: u32| glm::make_vec3(&mesh.vertices[3 * i as usize..3 * i as usize + 3]);
+        mesh.indices.chunks_exact(3).enumerate()
+            .filter_map(|(triangle, ids)| {
+                self.intersect_triangle(&vertex(ids[0]), &vertex(ids[1]), &vertex(ids[2]))
+                    .map(|(t, u, v)| (triangle, t, u, v))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+}
+
+impl SceneGraph {
+
+    // The nearest mesh the ray hits in the subtree below `from`. Uses the cached world matrices and
+    // bounds, so update_transforms has to have run since the scene last changed. Nodes without a
+    // mesh, hidden nodes and nodes outside of `layers` can not be hit.
+    pub fn raycast(&self, ray: &Ray, from: NodeId, layers: u32) -> Option<RayHit> {
+        let mut nearest: Option<RayHit> = None;
+        let mut stack = vec![from];
+        while let Some(id) = stack.pop() {
+            let node = &self[id];
+            if !node.visible {
+                continue;
+            }
+            match node.subtree_bounds() {
+                Some(bounds) if ray.intersect_aabb(&bounds.aabb).is_some_and(|t| closer(&nearest, t)) => {},
+                _ => continue,
+            }
+            stack.extend(node.children());
+
+            let mesh = match &node.mesh {
+                Some(resource) if node.layers & layers != 0 => &resource.mesh,
+                _ => continue,
+            };
+            let inverse_world = glm::inverse(node.world_matrix());
+            let local_ray = ray.transformed(&inverse_world);
+            if let Some((triangle, t, u, v)) = local_ray.intersect_mesh(mesh) {
+                let position = transform_point(node.world_matrix(), &local_ray.at(t));
+                let distance = glm::distance(&ray.origin, &position);
+                if closer(&nearest, distance) {
+                    let normal = interpolated_normal(mesh, triangle, u, v);
+                    nearest = Some(RayHit {
+                        node     : id,
+                        triangle,
+                        position,
+                        normal   : glm::normalize(&(node.normal_matrix() * normal)),
+                        distance,
+                    });
+                }
+            }
+        }
+        nearest
+    }
+
+}
+
+fn closer(nearest: &Option<RayHit>, distance: f32) -> bool {
+    nearest.as_ref().is_none_or(|hit| distance < hit.distance)
+}
+
+// The vertex normals blended across the triangle, or its face normal if the mesh has none
+fn interpolated_normal(mesh: &Mesh, triangle: usize, u: f32, v: f32) -> glm::Vec3 {
+    let ids = &mesh.indices[3 * triangle..3 * triangle + 3];
+    let attribute = |data: &[f32], i: u32| glm::make_vec3(&data[3 * i as usize..3 * i as usize + 3]);
+    if mesh.normals.len() == mesh.vertices.len() {
+        attribute(&mesh.normals, ids[0]) * (1.0 - u - v)
+            + attribute(&mesh.normals, ids[1]) * u
+            + attribute(&mesh.normals, ids[2]) * v
+    } else {
+        let a = attribute(&mesh.vertices, ids[0]);
+        let b = attribute(&mesh.vertices, ids[1]);
+        let c = attribute(&mesh.vertices, ids[2]);
+        glm::cross(&(b - a), &(c - a))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::Bounds;
+    use crate::mesh_registry::MeshRegistry;
+    use crate::scene_graph::{SceneNode, LAYER_ALL, LAYER_TERRAIN, LAYER_VEHICLES};
+
+    fn assert_near(a: glm::Vec3, b: glm::Vec3) {
+        assert!(glm::distance(&a, &b) < 1e-4, "{:?} is not {:?}", a, b);
+    }
+
+    fn unit_box() -> Aabb {
+        Bounds::from_points(&[-1.0, -1.0, -1.0, 1.0, 1.0, 1.0]).unwrap().aabb
+    }
+
+    // Squares 2 wide facing up, one at each of the given heights
+    fn floors(heights: &[f32]) -> Mesh {
+        let mut mesh = Mesh::empty();
+        for &y in heights {
+            let first = (mesh.vertices.len() / 3) as u32;
+            mesh.vertices.extend([-1.0, y, -1.0, 1.0, y, -1.0, 1.0, y, 1.0, -1.0, y, 1.0]);
+            mesh.normals.extend([0.0, 1.0, 0.0].iter().cycle().take(12));
+            mesh.colors.extend([1.0; 16]);
+            mesh.indices.extend([first, first + 2, first + 1, first, first + 3, first + 2]);
+        }
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh
+    }
+
+    #[test]
+    fn rays_enter_boxes_where_they_cross_them() {
+        let down = Ray::new(glm::vec3(0.5, 5.0, 0.5), glm::vec3(0.0, -1.0, 0.0));
+        assert_eq!(down.intersect_aabb(&unit_box()), Some(4.0));
+        let slanted = Ray::new(glm::vec3(-3.0, -3.0, 0.0), glm::vec3(1.0, 1.0, 0.0));
+        assert!((slanted.intersect_aabb(&unit_box()).unwrap() - 2.0 * 2f32.sqrt()).abs() < 1e-5);
+        // Starting inside counts as entering straight away
+        let inside = Ray::new(glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.3, 0.1, 1.0));
+        assert_eq!(inside.intersect_aabb(&unit_box()), Some(0.0));
+    }
+
+    #[test]
+    fn rays_miss_boxes_beside_and_behind_them() {
+        let beside = Ray::new(glm::vec3(2.0, 5.0, 0.0), glm::vec3(0.0, -1.0, 0.0));
+        assert_eq!(beside.intersect_aabb(&unit_box()), None);
+        let away = Ray::new(glm::vec3(0.0, 5.0, 0.0), glm::vec3(0.0, 1.0, 0.0));
+        assert_eq!(away.intersect_aabb(&unit_box()), None);
+        let past = Ray::new(glm::vec3(-3.0, 0.0, 0.0), glm::vec3(1.0, 1.0, 0.0));
+        assert_eq!(past.intersect_aabb(&unit_box()), None);
+    }
+
+    #[test]
+    fn rays_hit_triangles_from_both_sides() {
+        let (a, b, c) = (glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 1.0));
+        let down = Ray::new(glm::vec3(0.25, 2.0, 0.5), glm::vec3(0.0, -1.0, 0.0));
+        let (t, u, v) = down.intersect_triangle(&a, &b, &c).unwrap();
+        assert!((t - 2.0).abs() < 1e-6 && (u - 0.25).abs() < 1e-6 && (v - 0.5).abs() < 1e-6);
+        let up = Ray::new(glm::vec3(0.25, -2.0, 0.5), glm::vec3(0.0, 1.0, 0.0));
+        assert!(up.intersect_triangle(&a, &b, &c).is_some());
+    }
+
+    #[test]
+    fn rays_miss_triangles_beside_behind_and_along_them() {
+        let (a, b, c) = (glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 1.0));
+        let beside = Ray::new(glm::vec3(0.75, 2.0, 0.75), glm::vec3(0.0, -1.0, 0.0));
+        assert!(beside.intersect_triangle(&a, &b, &c).is_none());
+        let away = Ray::new(glm::vec3(0.25, 2.0, 0.25), glm::vec3(0.0, 1.0, 0.0));
+        assert!(away.intersect_triangle(&a, &b, &c).is_none());
+        let along = Ray::new(glm::vec3(-1.0, 0.0, 0.25), glm::vec3(1.0, 0.0, 0.0));
+        assert!(along.intersect_triangle(&a, &b, &c).is_none());
+    }
+
+    #[test]
+    fn meshes_are_hit_at_their_nearest_triangle() {
+        let mesh = floors(&[-1.0, 1.0]);
+        let ray = Ray::new(glm::vec3(0.2, 5.0, 0.3), glm::vec3(0.0, -1.0, 0.0));
+        let (triangle, t, _, _) = ray.intersect_mesh(&mesh).unwrap();
+        assert!((t - 4.0).abs() < 1e-5);
+        assert!(triangle >= 2, "Hit the lower floor");
+    }
+
+    #[test]
+    fn screen_rays_go_through_the_pixel() {
+        let projection = glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 1.0, 100.0);
+        let centre = Ray::from_screen(50.0, 50.0, 100.0, 100.0, &projection);
+        assert_near(centre.origin, glm::vec3(0.0, 0.0, -1.0));
+        assert_near(centre.direction, glm::vec3(0.0, 0.0, -1.0));
+        // The top left corner is up and to the left, a quarter turn across the view
+        let corner = Ray::from_screen(0.0, 0.0, 100.0, 100.0, &projection);
+        assert_near(corner.origin, glm::vec3(-1.0, 1.0, -1.0));
+        assert_near(corner.direction, glm::normalize(&glm::vec3(-1.0, 1.0, -1.0)));
+    }
+
+    // Two floors 2 wide, the upper vehicle one at y = 5 and the lower terrain one three times as large at y = 0
+    fn scene() -> (SceneGraph, NodeId, NodeId) {
+        let mut registry = MeshRegistry::without_gpu();
+        let floor = registry.load("floor", || Ok(floors(&[0.0]))).unwrap();
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let mut upper = SceneNode::new();
+        upper.mesh = Some(floor.clone());
+        upper.set_bounds(floor.bounds);
+        upper.position = glm::vec3(0.0, 5.0, 0.0);
+        upper.layers = LAYER_VEHICLES;
+        let upper = graph.add_child(root, upper);
+        let mut lower = SceneNode::new();
+        lower.mesh = Some(floor.clone());
+        lower.set_bounds(floor.bounds);
+        lower.scale = glm::vec3(3.0, 3.0, 3.0);
+        lower.layers = LAYER_TERRAIN;
+        let lower = graph.add_child(root, lower);
+        graph.update_transforms();
+        (graph, upper, lower)
+    }
+
+    #[test]
+    fn raycast_finds_the_nearest_node() {
+        let (graph, upper, lower) = scene();
+        let root = graph.root();
+        let down = Ray::new(glm::vec3(0.5, 10.0, 0.5), glm::vec3(0.0, -1.0, 0.0));
+        let hit = graph.raycast(&down, root, LAYER_ALL).unwrap();
+        assert_eq!(hit.node, upper);
+        assert!((hit.distance - 5.0).abs() < 1e-4);
+        assert_near(hit.position, glm::vec3(0.5, 5.0, 0.5));
+        assert_near(hit.normal, glm::vec3(0.0, 1.0, 0.0));
+
+        // Beside the upper floor, but over the scaled up lower one
+        let beside = Ray::new(glm::vec3(2.5, 10.0, 0.0), glm::vec3(0.0, -1.0, 0.0));
+        let hit = graph.raycast(&beside, root, LAYER_ALL).unwrap();
+        assert_eq!(hit.node, lower);
+        assert_near(hit.position, glm::vec3(2.5, 0.0, 0.0));
+
+        let away = Ray::new(glm::vec3(5.0, 10.0, 0.0), glm::vec3(0.0, -1.0, 0.0));
+        assert!(graph.raycast(&away, root, LAYER_ALL).is_none());
+    }
+
+    #[test]
+    fn raycast_skips_hidden_nodes_and_other_layers() {
+        let (mut graph, upper, lower) = scene();
+        let root = graph.root();
+        let down = Ray::new(glm::vec3(0.5, 10.0, 0.5), glm::vec3(0.0, -1.0, 0.0));
+        assert_eq!(graph.raycast(&down, root, LAYER_TERRAIN).unwrap().node, lower);
+        assert_eq!(graph.raycast(&down, root, LAYER_VEHICLES | LAYER_TERRAIN).unwrap().node, upper);
+        graph[upper].visible = false;
+        assert_eq!(graph.raycast(&down, root, LAYER_ALL).unwrap().node, lower);
+        graph[root].visible = false;
+        assert!(graph.raycast(&down, root, LAYER_ALL).is_none());
+    }
+}
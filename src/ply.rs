@@ -0,0 +1,425 @@
+use std::io::Write;
+
+use crate::mesh::Mesh;
+
+// Reading and writing Stanford PLY files, ASCII as well as binary of either byte order. Vertices
+// keep their positions, normals and colours, and get generated normals if they have none; faces with
+// more than three corners are split into triangle fans. Elements other than vertices and faces are
+// skipped.
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scalar {
+    I8, U8, I16, U16, I32, U32, F32, F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar, String> {
+        Ok(match name {
+            "char"   | "int8"    => Scalar::I8,
+            "uchar"  | "uint8"   => Scalar::U8,
+            "short"  | "int16"   => Scalar::I16,
+            "ushort" | "uint16"  => Scalar::U16,
+            "int"    | "int32"   => Scalar::I32,
+            "uint"   | "uint32"  => Scalar::U32,
+            "float"  | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(format!("Unknown PLY property type {}", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8  | Scalar::U8  => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    List(String, Scalar, Scalar),   // Name, type of the item count, type of the items
+}
+
+struct Element {
+    name       : String,
+    count      : usize,
+    properties : Vec<Property>,
+}
+
+// Where the values of the body come from, one at a time in the order the header declares them
+trait Values {
+    fn next(&mut self, scalar: Scalar) -> Result<f64, String>;
+}
+
+struct AsciiValues<'a> {
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> Values for AsciiValues<'a> {
+    fn next(&mut self, _scalar: Scalar) -> Result<f64, String> {
+        let token = self.tokens.next().ok_or("The PLY body ends early")?;
+        token.parse().map_err(|_| format!("Invalid number {} in the PLY body", token))
+    }
+}
+
+struct BinaryValues<'a> {
+    data       : &'a [u8],
+    big_endian : bool,
+}
+
+impl<'a> Values for BinaryValues<'a> {
+    fn next(&mut self, scalar: Scalar) -> Result<f64, String> {
+        let size = scalar.size();
+        if self.data.len() < size {
+            return Err(String::from("The PLY body ends early"));
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.data[..size]);
+        if self.big_endian {
+            bytes[..size].reverse();
+        }
+        self.data = &self.data[size..];
+        Ok(match scalar {
+            Scalar::I8  => bytes[0] as i8 as f64,
+            Scalar::U8  => bytes[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(bytes),
+        })
+    }
+}
+
+pub fn load_ply(path: &str) -> Result<Mesh, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    read_ply(&data).map_err(|e| format!("Failed to load {}: {}", path, e))
+}
+
+pub fn read_ply(data: &[u8]) -> Result<Mesh, String> {
+    // The header is text, read a line at a time up to the one that is just end_header. The body
+    // starts after that line's break, which is \n or \r\n.
+    let mut header = vec![];
+    let mut body = data;
+    loop {
+        if body.is_empty() {
+            return Err(String::from("No end_header in the PLY file"));
+        }
+        let line_end = body.iter().position(|&byte| byte == b'\n').unwrap_or(body.len());
+        let line = std::str::from_utf8(&body[..line_end]).map_err(|_| "The PLY header is not text")?.trim();
+        body = &body[(line_end + 1).min(body.len())..];
+        if line == "end_header" {
+            break;
+        }
+        header.push(line);
+    }
+
+    let mut lines = header.into_iter();
+    if lines.next() != Some("ply") {
+        return Err(String::from("Not a PLY file"));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(PlyFormat::BinaryBigEndian),
+            ["element", name, count] => elements.push(Element {
+                name       : name.to_string(),
+                count      : count.parse().map_err(|_| format!("Invalid element count {}", count))?,
+                properties : vec![],
+            }),
+            ["property", "list", count_type, item_type, name] => elements.last_mut()
+                .ok_or("PLY property outside of an element")?
+                .properties.push(Property::List(name.to_string(), Scalar::parse(count_type)?, Scalar::parse(item_type)?)),
+            ["property", scalar, name] => elements.last_mut()
+                .ok_or("PLY property outside of an element")?
+                .properties.push(Property::Scalar(name.to_string(), Scalar::parse(scalar)?)),
+            _ => {} // Comments, obj_info and blank lines
+        }
+    }
+
+    match format.ok_or("The PLY header has no format")? {
+        PlyFormat::Ascii => {
+            let text = std::str::from_utf8(body).map_err(|_| "The ASCII PLY body is not text")?;
+            read_body(&elements, &mut AsciiValues { tokens: text.split_ascii_whitespace() })
+        },
+        PlyFormat::BinaryLittleEndian => read_body(&elements, &mut BinaryValues { data: body, big_endian: false }),
+        PlyFormat::BinaryBigEndian => read_body(&elements, &mut BinaryValues { data: body, big_endian: true }),
+    }
+}
+
+fn read_body(elements: &[Element], values: &mut dyn Values) -> Result<Mesh, String> {
+    let mut vertices = vec![];
+    let mut normals = vec![];
+    let mut colors = vec![];
+    let mut indices = vec![];
+
+    for element in elements {
+        for _ in 0..element.count {
+            let mut scalars: Vec<(&str, Scalar, f64)> = vec![];
+            let mut lists: Vec<(&str, Vec<f64>)> = vec![];
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(name, scalar) => scalars.push((name, *scalar, values.next(*scalar)?)),
+                    Property::List(name, count_type, item_type) => {
+                        let count = values.next(*count_type)? as usize;
+                        let items = (0..count).map(|_| values.next(*item_type)).collect::<Result<Vec<_>, _>>()?;
+                        lists.push((name, items));
+                    },
+                }
+            }
+            let get = |wanted: &str| scalars.iter().find(|(name, _, _)| *name == wanted);
+
+            match element.name.as_str() {
+                "vertex" => {
+                    for axis in ["x", "y", "z"] {
+                        vertices.push(get(axis).ok_or(format!("PLY vertex without {}", axis))?.2 as f32);
+                    }
+                    if let (Some(x), Some(y), Some(z)) = (get("nx"), get("ny"), get("nz")) {
+                        normals.extend([x.2 as f32, y.2 as f32, z.2 as f32]);
+                    }
+                    if let (Some(r), Some(g), Some(b)) = (get("red"), get("green"), get("blue")) {
+                        // Integer channels run from 0 to 255, floating point ones from 0 to 1
+                        let channel = |&(_, scalar, value): &(&str, Scalar, f64)| {
+                            if is_float(scalar) { value as f32 } else { value as f32 / 255.0 }
+                        };
+                        colors.extend([channel(r), channel(g), channel(b), get("alpha").map_or(1.0, channel)]);
+                    }
+                },
+                "face" => {
+                    let corners = lists.iter()
+                        .find(|(name, _)| *name == "vertex_indices" || *name == "vertex_index")
+                        .ok_or("PLY face without vertex indices")?;
+                    let corners = corners.1.iter()
+                        .map(|&i| if i >= 0.0 && i.fract() == 0.0 {
+                            Ok(i as u32)
+                        } else {
+                            Err(format!("Invalid PLY vertex index {}", i))
+                        })
+                        .collect::<Result<Vec<u32>, String>>()?;
+                    for i in 1..corners.len().saturating_sub(1) {
+                        indices.extend([corners[0], corners[i], corners[i + 1]]);
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    let vertex_count = vertices.len() / 3;
+    if let Some(&out_of_range) = indices.iter().find(|&&i| i as usize >= vertex_count) {
+        return Err(format!("PLY face refers to vertex {} of {}", out_of_range, vertex_count));
+    }
+    if normals.len() != vertices.len() {
+        normals.clear();
+    }
+    if colors.len() != vertex_count * 4 {
+        colors = [1.0, 1.0, 1.0, 1.0].iter().cycle().take(vertex_count * 4).copied().collect();
+    }
+    let index_count = indices.len() as i32;
+    let mut mesh = Mesh { vertices, normals, colors, indices, index_count, ..Mesh::empty() };
+    mesh.ensure_normals();
+    Ok(mesh)
+}
+
+fn is_float(scalar: Scalar) -> bool {
+    scalar == Scalar::F32 || scalar == Scalar::F64
+}
+
+#[allow(dead_code)]
+pub fn save_ply(mesh: &Mesh, path: &str, format: PlyFormat) -> Result<(), String> {
+    let mut data = vec![];
+    write_ply(mesh, &mut data, format).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    std::fs::write(path, data).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+// Positions and indices always, normals if every vertex has one, and colours as bytes
+#[allow(dead_code)]
+pub fn write_ply<W: Write>(mesh: &Mesh, out: &mut W, format: PlyFormat) -> std::io::Result<()> {
+    let vertex_count = mesh.vertices.len() / 3;
+    let has_normals = mesh.normals.len() == mesh.vertices.len();
+    let has_colors = mesh.colors.len() == vertex_count * 4;
+    let format_name = match format {
+        PlyFormat::Ascii => "ascii",
+        PlyFormat::BinaryLittleEndian => "binary_little_endian",
+        PlyFormat::BinaryBigEndian => "binary_big_endian",
+    };
+
+    writeln!(out, "ply\nformat {} 1.0\ncomment Written by gloom-rs", format_name)?;
+    writeln!(out, "element vertex {}", vertex_count)?;
+    writeln!(out, "property float x\nproperty float y\nproperty float z")?;
+    if has_normals {
+        writeln!(out, "property float nx\nproperty float ny\nproperty float nz")?;
+    }
+    if has_colors {
+        writeln!(out, "property uchar red\nproperty uchar green\nproperty uchar blue\nproperty uchar alpha")?;
+    }
+    writeln!(out, "element face {}", mesh.indices.len() / 3)?;
+    writeln!(out, "property list uchar uint vertex_indices\nend_header")?;
+
+    let to_byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    for v in 0..vertex_count {
+        let position = &mesh.vertices[3 * v..3 * v + 3];
+        let normal = if has_normals { &mesh.normals[3 * v..3 * v + 3] } else { &[] };
+        let color: Vec<u8> = if has_colors { mesh.colors[4 * v..4 * v + 4].iter().map(|&c| to_byte(c)).collect() } else { vec![] };
+        match format {
+            PlyFormat::Ascii => {
+                let mut line: Vec<String> = position.iter().chain(normal).map(|x| x.to_string()).collect();
+                line.extend(color.iter().map(|c| c.to_string()));
+                writeln!(out, "{}", line.join(" "))?;
+            },
+            _ => {
+                let big_endian = format == PlyFormat::BinaryBigEndian;
+                for &x in position.iter().chain(normal) {
+                    out.write_all(&if big_endian { x.to_be_bytes() } else { x.to_le_bytes() })?;
+                }
+                out.write_all(&color)?;
+            },
+        }
+    }
+    for triangle in mesh.indices.chunks_exact(3) {
+        match format {
+            PlyFormat::Ascii => writeln!(out, "3 {} {} {}", triangle[0], triangle[1], triangle[2])?,
+            _ => {
+                let big_endian = format == PlyFormat::BinaryBigEndian;
+                out.write_all(&[3])?;
+                for &i in triangle {
+                    out.write_all(&if big_endian { i.to_be_bytes() } else { i.to_le_bytes() })?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A unit square in two triangles, with normals and colours that survive being stored as bytes
+    fn square() -> Mesh {
+        let mut mesh = Mesh::empty();
+        mesh.vertices = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0];
+        mesh.normals = [0.0, 0.0, 1.0].iter().cycle().take(12).copied().collect();
+        mesh.colors = vec![1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.2, 0.2, 0.4, 0.6, 1.0];
+        mesh.indices = vec![0, 1, 2, 0, 2, 3];
+        mesh.index_count = 6;
+        mesh
+    }
+
+    fn assert_near(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        assert!(a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-6), "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn round_trips_in_every_format() {
+        let mesh = square();
+        for &format in [PlyFormat::Ascii, PlyFormat::BinaryLittleEndian, PlyFormat::BinaryBigEndian].iter() {
+            let mut data = vec![];
+            write_ply(&mesh, &mut data, format).unwrap();
+            let read = read_ply(&data).unwrap_or_else(|e| panic!("{:?}: {}", format, e));
+            assert_eq!(read.vertices, mesh.vertices, "{:?}", format);
+            assert_eq!(read.normals, mesh.normals, "{:?}", format);
+            assert_near(&read.colors, &mesh.colors);
+            assert_eq!(read.indices, mesh.indices, "{:?}", format);
+            assert_eq!(read.index_count, 6);
+        }
+    }
+
+    #[test]
+    fn byte_orders_differ() {
+        let (mut little, mut big) = (vec![], vec![]);
+        write_ply(&square(), &mut little, PlyFormat::BinaryLittleEndian).unwrap();
+        write_ply(&square(), &mut big, PlyFormat::BinaryBigEndian).unwrap();
+        assert_ne!(little, big);
+        assert!(String::from_utf8_lossy(&big).contains("format binary_big_endian 1.0"));
+    }
+
+    const HEADER: &str = "ply\nformat ascii 1.0\ncomment A square and a pentagon\nelement vertex 5\n\
+        property float x\nproperty float y\nproperty float z\n";
+
+    #[test]
+    fn polygons_become_triangle_fans() {
+        let data = format!(
+            "{}element face 2\nproperty list uchar int vertex_indices\nend_header\n\
+             0 0 0\n1 0 0\n1 1 0\n0 1 0\n0.5 2 0\n4 0 1 2 3\n5 0 1 2 4 3\n",
+            HEADER,
+        );
+        let mesh = read_ply(data.as_bytes()).unwrap();
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3, 0, 1, 2, 0, 2, 4, 0, 4, 3]);
+        // No normals in the file, so they are generated
+        assert_eq!(mesh.normals.len(), mesh.vertices.len());
+        assert_eq!(mesh.colors, [1.0; 20]);
+    }
+
+    #[test]
+    fn colour_channels_are_read_by_their_type() {
+        let bytes = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\nend_header\n0 0 0 255 51 0\n";
+        assert_near(&read_ply(bytes.as_bytes()).unwrap().colors, &[1.0, 0.2, 0.0, 1.0]);
+
+        let floats = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\n\
+            property float red\nproperty float green\nproperty float blue\nproperty float alpha\nend_header\n\
+            0 0 0 1 0.2 0 0.5\n";
+        assert_near(&read_ply(floats.as_bytes()).unwrap().colors, &[1.0, 0.2, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn out_of_range_faces_are_errors() {
+        let data = format!(
+            "{}element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+             0 0 0\n1 0 0\n1 1 0\n0 1 0\n0.5 2 0\n3 0 1 5\n",
+            HEADER,
+        );
+        assert!(read_ply(data.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn negative_and_fractional_indices_are_errors() {
+        for face in ["3 0 -1 2", "3 0 1.5 2"].iter() {
+            let data = format!(
+                "{}element face 1\nproperty list uchar float vertex_indices\nend_header\n\
+                 0 0 0\n1 0 0\n1 1 0\n0 1 0\n0.5 2 0\n{}\n",
+                HEADER, face,
+            );
+            match read_ply(data.as_bytes()) {
+                Err(error) => assert!(error.contains("Invalid PLY vertex index"), "{}", error),
+                Ok(_) => panic!("Read the face {}", face),
+            }
+        }
+    }
+
+    #[test]
+    fn end_header_in_a_comment_does_not_end_the_header() {
+        let data = b"ply\nformat ascii 1.0\ncomment end_header is the last line\nelement vertex 3\n\
+            property float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\r\n\
+            0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        let mesh = read_ply(data).unwrap();
+        assert_eq!(mesh.vertices.len(), 9);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn broken_files_are_errors() {
+        assert!(read_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n").is_err());
+        assert!(read_ply(b"obj\nend_header\n").is_err());
+        let short = format!("{}end_header\n0 0 0\n1 0 0\n", HEADER);
+        assert!(read_ply(short.as_bytes()).is_err());
+    }
+}
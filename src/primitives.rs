@@ -0,0 +1,455 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mesh::Mesh;
+
+// Meshes made from scratch rather than loaded, for placeholders and debugging. All of them are
+// centred on the origin with Y up, painted in one colour, and come with normals, texture
+// coordinates and tangents. The round ones are closed and wrap the texture around the Y axis once,
+// with a seam where the coordinates start over.
+
+// One of the shapes below with its measurements, as scene files give it, e.g.
+// { "shape": "uv_sphere", "radius": 2.0, "segments": 16 }. How finely the surface is split up
+// can be left out.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum Primitive {
+    Cube      { size: f32, #[serde(default = "one")] subdivisions: u32 },
+    Plane     { width: f32, depth: f32, #[serde(default = "one")] columns: u32, #[serde(default = "one")] rows: u32 },
+    UvSphere  { radius: f32, #[serde(default = "segments")] segments: u32, #[serde(default = "rings")] rings: u32 },
+    Icosphere { radius: f32, #[serde(default = "subdivisions")] subdivisions: u32 },
+    Cylinder  { radius: f32, height: f32, #[serde(default = "segments")] segments: u32, #[serde(default = "one")] rings: u32 },
+    Cone      { radius: f32, height: f32, #[serde(default = "segments")] segments: u32, #[serde(default = "one")] rings: u32 },
+    Torus     { major_radius: f32, minor_radius: f32, #[serde(default = "segments")] segments: u32, #[serde(default = "rings")] sides: u32 },
+    Capsule   { radius: f32, height: f32, #[serde(default = "segments")] segments: u32, #[serde(default = "rings")] rings: u32 },
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn segments() -> u32 {
+    32
+}
+
+fn rings() -> u32 {
+    16
+}
+
+fn subdivisions() -> u32 {
+    3
+}
+
+impl Primitive {
+    pub fn mesh(&self, color: [f32; 4]) -> Mesh {
+        match *self {
+            Primitive::Cube { size, subdivisions } => cube(size, subdivisions, color),
+            Primitive::Plane { width, depth, columns, rows } => plane(width, depth, columns, rows, color),
+            Primitive::UvSphere { radius, segments, rings } => uv_sphere(radius, segments, rings, color),
+            Primitive::Icosphere { radius, subdivisions } => icosphere(radius, subdivisions, color),
+            Primitive::Cylinder { radius, height, segments, rings } => cylinder(radius, height, segments, rings, color),
+            Primitive::Cone { radius, height, segments, rings } => cone(radius, height, segments, rings, color),
+            Primitive::Torus { major_radius, minor_radius, segments, sides } => torus(major_radius, minor_radius, segments, sides, color),
+            Primitive::Capsule { radius, height, segments, rings } => capsule(radius, height, segments, rings, color),
+        }
+    }
+}
+
+// A corner of a grid: position, normal and texture coordinates
+type GridVertex = (glm::Vec3, glm::Vec3, glm::Vec2);
+
+// A surface made of `columns` by `rows` quads, with `vertex(i, j)` giving the corner at column i
+// and row j. The triangles face the way of d(vertex)/di x d(vertex)/dj.
+fn grid<F>(columns: u32, rows: u32, color: [f32; 4], vertex: F) -> Mesh
+where F: Fn(u32, u32) -> GridVertex
+{
+    let mut mesh = Mesh::empty();
+    for j in 0..=rows {
+        for i in 0..=columns {
+            let (position, normal, uv) = vertex(i, j);
+            mesh.vertices.extend(position.iter());
+            mesh.normals.extend(normal.iter());
+            mesh.uvs.extend(uv.iter());
+            mesh.colors.extend(color);
+        }
+    }
+    let at = |i: u32, j: u32| j * (columns + 1) + i;
+    for j in 0..rows {
+        for i in 0..columns {
+            mesh.indices.extend([at(i, j), at(i + 1, j), at(i + 1, j + 1)]);
+            mesh.indices.extend([at(i, j), at(i + 1, j + 1), at(i, j + 1)]);
+        }
+    }
+    finish(mesh)
+}
+
+fn finish(mut mesh: Mesh) -> Mesh {
+    mesh.index_count = mesh.indices.len() as i32;
+    mesh.ensure_tangents();
+    mesh
+}
+
+// A surface of revolution about the Y axis. `profile(j)` gives the distance from the axis, the
+// height, the normal's outward and upward parts and the v texture coordinate of row j, from the
+// bottom up.
+fn lathe<F>(segments: u32, rows: u32, color: [f32; 4], profile: F) -> Mesh
+where F: Fn(u32) -> (f32, f32, f32, f32, f32)
+{
+    let segments = segments.max(3);
+    grid(segments, rows, color, |i, j| {
+        let u = i as f32 / segments as f32;
+        let (sin, cos) = (u * TAU).sin_cos();
+        let (r, y, out, up, v) = profile(j);
+        let r = r.max(0.0);     // cos(PI / 2) is not quite 0 in floating point, and may even be below
+        (glm::vec3(r * sin, y, r * cos), glm::normalize(&glm::vec3(out * sin, up, out * cos)), glm::vec2(u, v))
+    })
+}
+
+// A flat round lid at height `y`, facing up or down, with the texture laid flat over it
+fn disc(radius: f32, y: f32, up: bool, segments: u32, rings: u32, color: [f32; 4]) -> Mesh {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    grid(segments, rings, color, |i, j| {
+        let (sin, cos) = (i as f32 / segments as f32 * TAU).sin_cos();
+        // Rim to centre facing up, centre to rim facing down
+        let r = radius * if up { 1.0 - j as f32 / rings as f32 } else { j as f32 / rings as f32 };
+        let normal = glm::vec3(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+        let uv = glm::vec2(0.5 + 0.5 * r / radius * sin, 0.5 + 0.5 * r / radius * if up { -cos } else { cos });
+        (glm::vec3(r * sin, y, r * cos), normal, uv)
+    })
+}
+
+// A cube with sides of `size`, each split into subdivisions x subdivisions quads with the whole
+// texture on it
+pub fn cube(size: f32, subdivisions: u32, color: [f32; 4]) -> Mesh {
+    let n = subdivisions.max(1);
+    let faces = [
+        glm::vec3(1.0, 0.0, 0.0), glm::vec3(-1.0, 0.0, 0.0),
+        glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, -1.0, 0.0),
+        glm::vec3(0.0, 0.0, 1.0), glm::vec3(0.0, 0.0, -1.0),
+    ];
+    Mesh::merge(faces.iter().map(|&normal| {
+        // Up the side faces, and towards -Z and +Z on the top and the bottom, with u to the right
+        let v_axis = if normal.y == 0.0 { glm::vec3(0.0, 1.0, 0.0) } else { glm::vec3(0.0, 0.0, -normal.y) };
+        let u_axis = glm::cross(&v_axis, &normal);
+        grid(n, n, color, |i, j| {
+            let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+            let position = (normal * 0.5 + u_axis * (u - 0.5) + v_axis * (v - 0.5)) * size;
+            (position, normal, glm::vec2(u, v))
+        })
+    }))
+}
+
+// A flat grid in the XZ plane facing up, `width` along X and `depth` along Z
+pub fn plane(width: f32, depth: f32, columns: u32, rows: u32, color: [f32; 4]) -> Mesh {
+    let (columns, rows) = (columns.max(1), rows.max(1));
+    grid(columns, rows, color, |i, j| {
+        let (u, v) = (i as f32 / columns as f32, j as f32 / rows as f32);
+        (glm::vec3((u - 0.5) * width, 0.0, (0.5 - v) * depth), glm::vec3(0.0, 1.0, 0.0), glm::vec2(u, v))
+    })
+}
+
+// A sphere of `segments` around and `rings` from pole to pole
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32, color: [f32; 4]) -> Mesh {
+    let rings = rings.max(2);
+    lathe(segments, rings, color, |j| {
+        let v = j as f32 / rings as f32;
+        let (sin, cos) = (v * PI - FRAC_PI_2).sin_cos();
+        (radius * cos, radius * sin, cos, sin, v)
+    })
+}
+
+// A sphere made by splitting the faces of an icosahedron into four, `subdivisions` times over.
+// The triangles are all about the same size, unlike those of a UV sphere.
+pub fn icosphere(radius: f32, subdivisions: u32, color: [f32; 4]) -> Mesh {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut points: Vec<glm::Vec3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ].iter().map(|&(x, y, z)| glm::normalize(&glm::vec3(x, y, z))).collect();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, points: &mut Vec<glm::Vec3>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push(glm::normalize(&(points[a as usize] + points[b as usize])));
+                points.len() as u32 - 1
+            })
+        };
+        triangles = triangles.iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b, &mut points), midpoint(b, c, &mut points), midpoint(c, a, &mut points));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let mut mesh = Mesh::empty();
+    for p in &points {
+        let u = p.x.atan2(p.z) / TAU;
+        mesh.vertices.extend((p * radius).iter());
+        mesh.normals.extend(p.iter());
+        mesh.uvs.extend([u - u.floor(), 0.5 + p.y.asin() / PI]);
+        mesh.colors.extend(color);
+    }
+    mesh.indices = triangles.concat();
+
+    // The poles have no direction around the axis, and no u to go with it
+    let pole_count = mesh.vertices.len() / 3;
+    let poles: Vec<bool> = (0..pole_count)
+        .map(|i| mesh.normals[3 * i].abs() < 1e-6 && mesh.normals[3 * i + 2].abs() < 1e-6)
+        .collect();
+    let is_pole = |vertex: u32| poles.get(vertex as usize).copied().unwrap_or(false);
+
+    // Triangles across the seam would have the whole texture squeezed into them backwards, so
+    // their corners near u = 0 get copies further along, past u = 1
+    let mut wrapped: HashMap<u32, u32> = HashMap::new();
+    for triangle in (0..mesh.indices.len()).step_by(3) {
+        let us: Vec<Option<f32>> = mesh.indices[triangle..triangle + 3].iter()
+            .map(|&i| if is_pole(i) { None } else { Some(mesh.uvs[2 * i as usize]) })
+            .collect();
+        let known = us.iter().flatten();
+        let span = known.clone().cloned().fold(f32::MIN, f32::max) - known.cloned().fold(f32::MAX, f32::min);
+        if span <= 0.5 {
+            continue;
+        }
+        for corner in (0..3).filter(|&corner| us[corner].is_some_and(|u| u < 0.5)) {
+            let vertex = mesh.indices[triangle + corner];
+            let copy = match wrapped.get(&vertex) {
+                Some(&copy) => copy,
+                None => {
+                    let copy = mesh.duplicate_vertex(vertex);
+                    mesh.uvs[2 * copy as usize] += 1.0;
+                    wrapped.insert(vertex, copy);
+                    copy
+                },
+            };
+            mesh.indices[triangle + corner] = copy;
+        }
+    }
+
+    // So each triangle there gets a pole of its own, lined up with the triangle's other corners
+    let mut poles_used = vec![false; pole_count];
+    for triangle in (0..mesh.indices.len()).step_by(3) {
+        for corner in 0..3 {
+            let vertex = mesh.indices[triangle + corner] as usize;
+            if is_pole(vertex as u32) {
+                let others = [(corner + 1) % 3, (corner + 2) % 3].map(|other| mesh.indices[triangle + other] as usize);
+                let pole = if poles_used[vertex] { mesh.duplicate_vertex(vertex as u32) } else { vertex as u32 };
+                poles_used[vertex] = true;
+                mesh.uvs[2 * pole as usize] = (mesh.uvs[2 * others[0]] + mesh.uvs[2 * others[1]]) / 2.0;
+                mesh.indices[triangle + corner] = pole;
+            }
+        }
+    }
+    finish(mesh)
+}
+
+// An upright cylinder with flat ends, `segments` around and `rings` from end to end
+pub fn cylinder(radius: f32, height: f32, segments: u32, rings: u32, color: [f32; 4]) -> Mesh {
+    let rings = rings.max(1);
+    let side = lathe(segments, rings, color, |j| {
+        let v = j as f32 / rings as f32;
+        (radius, (v - 0.5) * height, 1.0, 0.0, v)
+    });
+    let top = disc(radius, height / 2.0, true, segments, 1, color);
+    let bottom = disc(radius, -height / 2.0, false, segments, 1, color);
+    Mesh::merge([side, top, bottom])
+}
+
+// An upright cone with its point at the top and a flat base, `segments` around and `rings` from
+// the base to the point
+pub fn cone(radius: f32, height: f32, segments: u32, rings: u32, color: [f32; 4]) -> Mesh {
+    let rings = rings.max(1);
+    // The side leans in, so its normals lean up by as much
+    let side = lathe(segments, rings, color, |j| {
+        let v = j as f32 / rings as f32;
+        (radius * (1.0 - v), (v - 0.5) * height, height, radius, v)
+    });
+    let bottom = disc(radius, -height / 2.0, false, segments, rings, color);
+    Mesh::merge([side, bottom])
+}
+
+// A ring of tube around the Y axis, `major_radius` from the axis to the middle of the tube, which
+// is `minor_radius` thick. `segments` go around the axis, `sides` around the tube.
+pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, sides: u32, color: [f32; 4]) -> Mesh {
+    let (segments, sides) = (segments.max(3), sides.max(3));
+    grid(segments, sides, color, |i, j| {
+        let (u, v) = (i as f32 / segments as f32, j as f32 / sides as f32);
+        let (sin_u, cos_u) = (u * TAU).sin_cos();
+        let (sin_v, cos_v) = (v * TAU).sin_cos();
+        let r = major_radius + minor_radius * cos_v;
+        let normal = glm::vec3(cos_v * sin_u, sin_v, cos_v * cos_u);
+        (glm::vec3(r * sin_u, minor_radius * sin_v, r * cos_u), normal, glm::vec2(u, v))
+    })
+}
+
+// An upright cylinder of `height` with half spheres on the ends, `segments` around and `rings`
+// from the equator to the pole of each half sphere. The texture is stretched over the whole length.
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32, color: [f32; 4]) -> Mesh {
+    let rings = rings.max(1);
+    let length = height + 2.0 * radius;
+    // Rows 0 to rings go up the lower half sphere, the rest up the upper one, and the quads
+    // between the two equators are the cylinder
+    lathe(segments, 2 * rings + 1, color, |j| {
+        let (angle, centre) = if j <= rings {
+            (j as f32 / rings as f32 * FRAC_PI_2 - FRAC_PI_2, -height / 2.0)
+        } else {
+            ((j - rings - 1) as f32 / rings as f32 * FRAC_PI_2, height / 2.0)
+        };
+        let (sin, cos) = angle.sin_cos();
+        let y = centre + radius * sin;
+        (radius * cos, y, cos, sin, (y + length / 2.0) / length)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+    fn position(mesh: &Mesh, vertex: u32) -> glm::Vec3 {
+        glm::make_vec3(&mesh.vertices[3 * vertex as usize..3 * vertex as usize + 3])
+    }
+
+    fn counts(mesh: &Mesh) -> (usize, usize) {
+        (mesh.vertices.len() / 3, mesh.indices.len())
+    }
+
+    // Every vertex has all its attributes, and every normal is a unit vector
+    fn assert_complete(mesh: &Mesh) {
+        let count = mesh.vertices.len() / 3;
+        assert_eq!(mesh.normals.len(), 3 * count);
+        assert_eq!(mesh.uvs.len(), 2 * count);
+        assert_eq!(mesh.tangents.len(), 4 * count);
+        assert_eq!(mesh.colors.len(), 4 * count);
+        assert_eq!(mesh.index_count as usize, mesh.indices.len());
+        assert!(mesh.indices.iter().all(|&i| (i as usize) < count));
+        for normal in mesh.normals.chunks_exact(3) {
+            assert!((glm::length(&glm::make_vec3(normal)) - 1.0).abs() < 1e-5, "{:?}", normal);
+        }
+    }
+
+    // Every triangle with an area faces away from `inside(centroid)`, the point it should face away from
+    fn assert_outward<F: Fn(&glm::Vec3) -> glm::Vec3>(mesh: &Mesh, inside: F) {
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [position(mesh, triangle[0]), position(mesh, triangle[1]), position(mesh, triangle[2])];
+            let face = glm::cross(&(b - a), &(c - a));
+            if glm::length(&face) < 1e-6 {
+                continue;   // The corners of a grid squeezed into a pole or a point
+            }
+            let centroid = (a + b + c) / 3.0;
+            assert!(glm::dot(&face, &(centroid - inside(&centroid))) > 0.0, "{:?} faces in", triangle);
+        }
+    }
+
+    fn from_origin(_: &glm::Vec3) -> glm::Vec3 {
+        glm::zero()
+    }
+
+    #[test]
+    fn cubes_have_a_grid_on_each_face() {
+        let cube = cube(2.0, 3, WHITE);
+        assert_eq!(counts(&cube), (6 * 16, 6 * 9 * 6));
+        assert_complete(&cube);
+        assert_outward(&cube, from_origin);
+        assert!(cube.vertices.iter().all(|&x| x.abs() <= 1.0 + 1e-6));
+    }
+
+    #[test]
+    fn planes_face_up() {
+        let plane = plane(4.0, 2.0, 4, 2, WHITE);
+        assert_eq!(counts(&plane), (15, 4 * 2 * 6));
+        assert_complete(&plane);
+        assert_outward(&plane, |centroid| centroid - glm::vec3(0.0, 1.0, 0.0));
+        assert!(plane.normals.chunks_exact(3).all(|n| n == [0.0, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn uv_spheres_are_round() {
+        let sphere = uv_sphere(2.0, 12, 6, WHITE);
+        assert_eq!(counts(&sphere), (13 * 7, 12 * 6 * 6));
+        assert_complete(&sphere);
+        assert_outward(&sphere, from_origin);
+        for vertex in 0..sphere.vertices.len() as u32 / 3 {
+            assert!((glm::length(&position(&sphere, vertex)) - 2.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn icospheres_give_each_triangle_at_a_pole_a_pole_of_its_own() {
+        let sphere = icosphere(2.0, 2, WHITE);
+        assert_eq!(sphere.indices.len(), 20 * 16 * 3);
+        assert_complete(&sphere);
+        assert_outward(&sphere, from_origin);
+
+        for &y in [2.0, -2.0].iter() {
+            let poles: Vec<u32> = (0..sphere.vertices.len() as u32 / 3)
+                .filter(|&vertex| glm::distance(&position(&sphere, vertex), &glm::vec3(0.0, y, 0.0)) < 1e-5)
+                .collect();
+            // Six triangles meet at each pole
+            assert_eq!(poles.len(), 6);
+            for &pole in &poles {
+                let corner = sphere.indices.iter().position(|&i| i == pole).unwrap();
+                assert_eq!(sphere.indices.iter().filter(|&&i| i == pole).count(), 1);
+                // Halfway between the other two corners around the axis
+                let triangle = &sphere.indices[corner / 3 * 3..corner / 3 * 3 + 3];
+                let others: Vec<f32> = triangle.iter().filter(|&&i| i != pole).map(|&i| sphere.uvs[2 * i as usize]).collect();
+                assert!((sphere.uvs[2 * pole as usize] - (others[0] + others[1]) / 2.0).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn cylinders_and_cones_are_closed_at_the_bottom() {
+        let cylinder = cylinder(1.0, 3.0, 8, 2, WHITE);
+        assert_eq!(counts(&cylinder), (9 * 3 + 2 * 9 * 2, 8 * 2 * 6 + 2 * 8 * 6));
+        assert_complete(&cylinder);
+        assert_outward(&cylinder, from_origin);
+
+        let cone = cone(1.0, 3.0, 8, 2, WHITE);
+        assert_eq!(counts(&cone), (2 * 9 * 3, 2 * 8 * 2 * 6));
+        assert_complete(&cone);
+        assert_outward(&cone, from_origin);
+        let top = (0..cone.vertices.len() as u32 / 3).map(|vertex| position(&cone, vertex).y).fold(f32::MIN, f32::max);
+        assert!((top - 1.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tori_face_away_from_the_middle_of_the_tube() {
+        let torus = torus(3.0, 1.0, 12, 8, WHITE);
+        assert_eq!(counts(&torus), (13 * 9, 12 * 8 * 6));
+        assert_complete(&torus);
+        assert_outward(&torus, |centroid| glm::normalize(&glm::vec3(centroid.x, 0.0, centroid.z)) * 3.0);
+    }
+
+    #[test]
+    fn capsules_are_as_long_as_their_height_and_two_radii() {
+        let capsule = capsule(1.0, 2.0, 8, 4, WHITE);
+        assert_eq!(counts(&capsule), (9 * 10, 8 * 9 * 6));
+        assert_complete(&capsule);
+        assert_outward(&capsule, |centroid| glm::vec3(0.0, centroid.y.clamp(-1.0, 1.0), 0.0));
+        let ys: Vec<f32> = (0..capsule.vertices.len() as u32 / 3).map(|vertex| position(&capsule, vertex).y).collect();
+        assert!((ys.iter().cloned().fold(f32::MIN, f32::max) - 2.0).abs() < 1e-6);
+        assert!((ys.iter().cloned().fold(f32::MAX, f32::min) + 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn scene_files_describe_primitives_by_shape() {
+        let primitive: Primitive = serde_json::from_str(r#"{ "shape": "torus", "major_radius": 3.0, "minor_radius": 1.0 }"#).unwrap();
+        assert_eq!(primitive, Primitive::Torus { major_radius: 3.0, minor_radius: 1.0, segments: 32, sides: 16 });
+        assert_eq!(counts(&primitive.mesh(WHITE)), (33 * 17, 32 * 16 * 6));
+        assert!(serde_json::from_str::<Primitive>(r#"{ "shape": "teapot" }"#).is_err());
+    }
+}
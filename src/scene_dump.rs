@@ -0,0 +1,130 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::scene_graph::{EulerOrder, NodeId, SceneGraph, SceneNode};
+
+// Human readable views of a whole hierarchy, for debugging. Both compose the world transforms from
+// the nodes' fields, so they show where things are now even if update_transforms has not run.
+
+// Position, rotation (Euler XYZ, in degrees) and scale pulled back out of a transformation
+fn describe_transform(matrix: &glm::Mat4) -> String {
+    let mut parts = SceneNode::new();
+    parts.set_local_matrix(matrix);
+    let p = parts.position;
+    let r = parts.euler(EulerOrder::XYZ).map(|angle| angle.to_degrees() + 0.0); // No -0.0
+    let s = parts.scale;
+    format!(
+        "position [{:.2}, {:.2}, {:.2}]  rotation [{:.1}, {:.1}, {:.1}]  scale [{:.2}, {:.2}, {:.2}]",
+        p.x, p.y, p.z, r.x, r.y, r.z, s.x, s.y, s.z,
+    )
+}
+
+// What the node draws, e.g. "body.obj#Body (vao 3, 1200 indices, 10 users)", or "nothing"
+fn describe_drawable(node: &SceneNode) -> String {
+    match &node.mesh {
+        Some(mesh) => format!(
+            "{} (vao {}, {} indices, {} users)",
+            mesh.name, mesh.gpu.vao_id, mesh.gpu.index_count, Rc::strong_count(mesh),
+        ),
+        None => String::from("nothing"),
+    }
+}
+
+impl SceneGraph {
+
+    // The subtree below `from` as indented text, one block per node:
+    //
+    // heli_0 (3 children) draws body (vao 2, 5712 indices, 10 users)
+    //     local: position [62.50, 31.00, -80.00]  rotation [0.0, 0.0, 0.0]  scale [1.00, 1.00, 1.00]
+    //     world: position [62.50, 31.00, -80.00]  rotation [0.0, 0.0, 0.0]  scale [1.00, 1.00, 1.00]
+    //     door (0 children) draws ...
+    pub fn dump(&self, from: NodeId) -> String {
+        let mut text = String::new();
+        for visit in self.depth_first(from) {
+            let node = visit.node;
+            let indent = "    ".repeat(visit.depth);
+            let hidden = if node.visible { "" } else { " [hidden]" };
+            // Writing to a String can not fail
+            writeln!(
+                text,
+                "{}{} ({} children) draws {}{}",
+                indent,
+                node.name.as_deref().unwrap_or("-"),
+                node.get_n_children(),
+                describe_drawable(node),
+                hidden,
+            ).unwrap();
+            writeln!(text, "{}    local: {}", indent, describe_transform(&node.compose_local_matrix())).unwrap();
+            writeln!(text, "{}    world: {}", indent, describe_transform(&visit.world)).unwrap();
+        }
+        text
+    }
+
+    pub fn print_tree(&self, from: NodeId) {
+        print!("{}", self.dump(from));
+    }
+
+    // The subtree below `from` in Graphviz' DOT language, to be rendered with e.g.
+    // `dot -Tsvg scene.dot -o scene.svg`. Hidden nodes are drawn dashed. A child whose parent link
+    // does not point back at the node listing it is a broken hierarchy, and its edge is drawn red.
+    pub fn to_dot(&self, from: NodeId) -> String {
+        let mut text = String::from("digraph scene {\n    node [shape=box, fontname=\"monospace\"];\n");
+        let mut numbers: HashMap<NodeId, usize> = HashMap::new();
+        for visit in self.depth_first(from) {
+            let number = numbers.len();
+            numbers.insert(visit.id, number);
+            let node = visit.node;
+            let label = escape(&format!("{}\n{}", node.name.as_deref().unwrap_or("-"), describe_drawable(node)));
+            let style = if node.visible { "solid" } else { "dashed" };
+            writeln!(text, "    n{} [label=\"{}\", style={}];", number, label, style).unwrap();
+
+            if let Some(parent) = node.parent().and_then(|parent| numbers.get(&parent)) {
+                writeln!(text, "    n{} -> n{};", parent, number).unwrap();
+            }
+        }
+        // Children the traversal reached through a parent they don't acknowledge
+        for visit in self.depth_first(from) {
+            for &child in visit.node.children() {
+                if self[child].parent() != Some(visit.id) {
+                    writeln!(text, "    n{} -> n{} [color=red];", numbers[&visit.id], numbers[&child]).unwrap();
+                }
+            }
+        }
+        text.push_str("}\n");
+        text
+    }
+
+}
+
+// The text as a quoted DOT label. Backslashes in e.g. Windows paths are kept as they are rather than
+// read as DOT's own escapes, and line breaks become DOT's centred ones.
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace("\r\n", "\\n").replace(['\r', '\n'], "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Mesh;
+    use crate::mesh_registry::MeshRegistry;
+
+    #[test]
+    fn dot_labels_are_escaped() {
+        let mut registry = MeshRegistry::without_gpu();
+        let mesh = registry.load("C:\\res\\rotor.obj#\"blade\"\r\nend", || Ok(Mesh::empty())).unwrap();
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        graph.add_child(root, SceneNode::with_mesh(mesh).named("a \"quoted\" name"));
+
+        let dot = graph.to_dot(root);
+        let line = dot.lines().find(|line| line.contains("quoted")).unwrap();
+        assert_eq!(
+            line,
+            "    n1 [label=\"a \\\"quoted\\\" name\\nC:\\\\res\\\\rotor.obj#\\\"blade\\\"\\nend (vao 0, 0 indices, 1 users)\", style=solid];",
+        );
+        assert_eq!(dot.lines().count(), 6);
+    }
+}
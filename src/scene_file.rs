@@ -0,0 +1,373 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::animation::FlightPath;
+use crate::articulated::ModelDescriptor;
+use crate::heightmap::HeightmapOptions;
+use crate::mesh_registry::{MeshFiles, MeshRegistry};
+use crate::primitives::Primitive;
+use crate::scene_graph::{quat_to_euler, EulerOrder, NodeId, SceneGraph, SceneNode, LAYER_DEFAULT};
+
+// A declarative description of a scene, stored as JSON. It lists the meshes the scene uses and the
+// node hierarchy placed below the root of the graph. A small example:
+//
+// {
+//   "meshes": [
+//     { "name": "terrain", "path": "lunarsurface.obj" },
+//     { "name": "body", "path": "helicopter.obj", "object": "Body_body", "color": [0.3, 0.3, 0.3, 1.0] }
+//   ],
+//   "nodes": [
+//     { "name": "terrain", "mesh": "terrain" },
+//     { "name": "heli_0", "mesh": "body", "position": [62.5, 31.0, -80.0], "children": [] }
+//   ]
+// }
+//
+// Mesh paths are relative to the scene file. Rotations are Euler angles in radians, in XYZ order.
+// Meshes can also come from glTF files (.gltf or .glb), where "object" names a mesh in the file,
+// and from PLY and STL files. For those the colour tints the colours in the file instead of
+// replacing them. A mesh with "heightmap": { "spacing": 2.0, "height_scale": 40.0, "skirt": 5.0 }
+// is terrain made from the image at its path, and one with "primitive": { "shape": "torus", ... }
+// and no path is made from scratch, see primitives.rs. A node can also have a "model", an articulated model
+// descriptor (see articulated.rs) or a .gltf or .glb file, whose parts are built below it.
+// Nodes can also be hidden with "visible": false and put in other render layers with "layers".
+// A node with "flight_path": { "time_offset": 2.0 } flies a circuit around where it is placed.
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MeshEntry {
+    pub name : String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path : String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object : Option<String>,            // Which object of the file, or all of them merged if None
+    #[serde(default = "white")]
+    pub color : [f32; 4],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heightmap : Option<HeightmapOptions>,   // Read the path as a heightmap image, see heightmap.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primitive : Option<Primitive>,      // Generate the mesh instead of reading a file
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct FlightPathEntry {
+    #[serde(default)]
+    pub time_offset : f32,                  // Seconds ahead of the others on the same circuit
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NodeEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name : Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh : Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color : Option<[f32; 4]>,           // Paints the mesh in another colour than its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model : Option<String>,             // An articulated model descriptor or a glTF file, built below the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flight_path : Option<FlightPathEntry>,  // Flies the node around its position, see animation.rs
+
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub position : [f32; 3],
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rotation : [f32; 3],
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub scale : [f32; 3],
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub reference_point : [f32; 3],
+
+    #[serde(default = "visible", skip_serializing_if = "is_visible")]
+    pub visible : bool,
+    #[serde(default = "default_layers", skip_serializing_if = "is_default_layers")]
+    pub layers : u32,                       // Bitmask of render layers, see scene_graph::LAYER_*
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children : Vec<NodeEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SceneFile {
+    #[serde(default)]
+    pub meshes : Vec<MeshEntry>,
+    #[serde(default)]
+    pub nodes  : Vec<NodeEntry>,
+
+    #[serde(skip)]
+    base_dir : PathBuf,                     // Where mesh paths are resolved from
+}
+
+// What instantiating a scene file uploaded and built, so the graph can be saved back out afterwards
+#[derive(Default)]
+pub struct LoadedScene {
+    pub meshes  : Vec<MeshEntry>,
+    sources     : HashMap<String, (String, [f32; 4])>, // Registry name -> the mesh and colour that went into it
+    models      : HashMap<NodeId, String>,  // Nodes with a model -> the descriptor
+    model_parts : HashSet<NodeId>,          // The top parts of those models, which are not written back
+}
+
+fn white() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+fn one() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn visible() -> bool {
+    true
+}
+
+fn is_visible(visible: &bool) -> bool {
+    *visible
+}
+
+fn default_layers() -> u32 {
+    LAYER_DEFAULT
+}
+
+fn is_default_layers(layers: &u32) -> bool {
+    *layers == LAYER_DEFAULT
+}
+
+fn is_zero(v: &[f32; 3]) -> bool {
+    *v == [0.0; 3]
+}
+
+fn is_one(v: &[f32; 3]) -> bool {
+    *v == one()
+}
+
+impl SceneFile {
+
+    pub fn load(path: &str) -> Result<SceneFile, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read scene file {}: {}", path, e))?;
+        let mut scene: SceneFile = serde_json::from_str(&text)
+            .map_err(|e| format!("Failed to parse scene file {}: {}", path, e))?;
+        scene.base_dir = Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(scene)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize scene: {}", e))?;
+        std::fs::write(path, text)
+            .map_err(|e| format!("Failed to write scene file {}: {}", path, e))
+    }
+
+    // Loads the meshes and builds the node hierarchy below `parent`. Meshes come from the registry,
+    // so every mesh is only uploaded once per colour it is drawn in, also across scene files.
+    // On an error the nodes built so far are taken out of the graph again.
+    pub fn instantiate(&self, graph: &mut SceneGraph, parent: NodeId, registry: &mut MeshRegistry) -> Result<LoadedScene, String> {
+        let mut tops = vec![];
+        match self.build(graph, parent, registry, &mut tops) {
+            Ok(loaded) => Ok(loaded),
+            Err(e) => {
+                for id in tops {
+                    graph.despawn(id)?;
+                }
+                Err(e)
+            },
+        }
+    }
+
+    // Does the work of instantiate, keeping track of the nodes it adds directly below `parent`
+    fn build(&self, graph: &mut SceneGraph, parent: NodeId, registry: &mut MeshRegistry, tops: &mut Vec<NodeId>) -> Result<LoadedScene, String> {
+        let top = parent;
+        let mut files = MeshFiles::default();
+        let mut descriptors: HashMap<&str, ModelDescriptor> = HashMap::new();
+        let mut loaded = LoadedScene { meshes: self.meshes.clone(), ..LoadedScene::default() };
+        let mut stack: Vec<(&NodeEntry, NodeId)> = self.nodes.iter().rev().map(|entry| (entry, parent)).collect();
+        while let Some((entry, parent)) = stack.pop() {
+            let mut node = SceneNode::new();
+            if let Some(mesh_name) = &entry.mesh {
+                let mesh_entry = self.meshes.iter().find(|m| &m.name == mesh_name)
+                    .ok_or(format!("Node uses unknown mesh {}", mesh_name))?;
+                let color = entry.color.unwrap_or(mesh_entry.color);
+                let path = self.base_dir.join(&mesh_entry.path);
+                let mesh = match (&mesh_entry.primitive, &mesh_entry.heightmap) {
+                    (Some(primitive), _) => {
+                        let name = format!("primitive {:?} {:?}", primitive, color);
+                        registry.load(&name, || Ok(primitive.mesh(color)))
+                    },
+                    (None, Some(options)) => files.load_heightmap(registry, &path, options, color),
+                    (None, None) => files.load(registry, &path, mesh_entry.object.as_deref(), color),
+                }
+                .map_err(|e| format!("Mesh {}: {}", mesh_name, e))?;
+                loaded.sources.insert(mesh.name.clone(), (mesh_name.clone(), color));
+                node = SceneNode::with_mesh(mesh);
+            }
+            node.name = entry.name.clone();
+            node.position = entry.position.into();
+            node.set_euler(&entry.rotation.into(), EulerOrder::XYZ);
+            node.scale = entry.scale.into();
+            node.reference_point = entry.reference_point.into();
+            node.visible = entry.visible;
+            node.layers = entry.layers;
+            if let Some(path) = entry.flight_path {
+                node.components.insert(FlightPath {
+                    start         : node.position,
+                    rest_rotation : node.rotation,
+                    time_offset   : path.time_offset,
+                });
+            }
+
+            let id = graph.add_child(parent, node);
+            if parent == top {
+                tops.push(id);
+            }
+            if let Some(model) = &entry.model {
+                let path = self.base_dir.join(model);
+                let parts = match path.extension().and_then(|extension| extension.to_str()) {
+                    Some("gltf") | Some("glb") => files.gltf(&path)?.instantiate(graph, id, registry)?,
+                    _ => {
+                        if !descriptors.contains_key(model.as_str()) {
+                            descriptors.insert(model, ModelDescriptor::load(&path.to_string_lossy())?);
+                        }
+                        vec![descriptors[model.as_str()].instantiate_with(graph, id, registry, &mut files)?]
+                    },
+                };
+                loaded.models.insert(id, model.clone());
+                loaded.model_parts.extend(parts);
+            }
+            stack.extend(entry.children.iter().rev().map(|child| (child, id)));
+        }
+        Ok(loaded)
+    }
+
+    // Describes everything below `from` in the graph. Nodes drawing a mesh that `loaded` does not
+    // know about are written without one.
+    pub fn capture(graph: &SceneGraph, from: NodeId, loaded: &LoadedScene) -> SceneFile {
+        SceneFile {
+            meshes   : loaded.meshes.clone(),
+            nodes    : graph[from].children().iter().map(|&child| capture_node(graph, child, loaded)).collect(),
+            base_dir : PathBuf::new(),
+        }
+    }
+
+}
+
+fn capture_node(graph: &SceneGraph, id: NodeId, loaded: &LoadedScene) -> NodeEntry {
+    let node = &graph[id];
+    let (mesh, color) = match node.mesh.as_ref().and_then(|mesh| loaded.sources.get(&mesh.name)) {
+        Some((mesh_name, color)) => {
+            let mesh_color = loaded.meshes.iter().find(|m| &m.name == mesh_name).map(|m| m.color);
+            let color = if mesh_color == Some(*color) { None } else { Some(*color) };
+            (Some(mesh_name.clone()), color)
+        },
+        _ => (None, None),
+    };
+    // A flying node is written where its circuit is and turned the way it was placed, not as it
+    // has got to on the way
+    let flight_path = node.components.get::<FlightPath>();
+    let position = flight_path.map_or(node.position, |path| path.start);
+    let rotation = flight_path.map_or(node.rotation, |path| path.rest_rotation);
+    NodeEntry {
+        name            : node.name.clone(),
+        mesh,
+        color,
+        model           : loaded.models.get(&id).cloned(),
+        flight_path     : flight_path.map(|path| FlightPathEntry { time_offset: path.time_offset }),
+        position        : position.into(),
+        rotation        : (quat_to_euler(&rotation, EulerOrder::XYZ) + glm::vec3(0.0, 0.0, 0.0)).into(), // No -0.0 in the file
+        scale           : node.scale.into(),
+        reference_point : node.reference_point.into(),
+        visible         : node.visible,
+        layers          : node.layers,
+        children        : node.children().iter()
+            .filter(|child| !loaded.model_parts.contains(child))
+            .map(|&child| capture_node(graph, child, loaded))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::animation;
+
+    #[test]
+    fn failing_node_takes_the_scene_out_again() {
+        let scene: SceneFile = serde_json::from_str(r#"{ "nodes": [
+            { "name": "first", "children": [ { "name": "child" } ] },
+            { "name": "second", "mesh": "unknown" } ] }"#).unwrap();
+        let mut graph = SceneGraph::new();
+        let mut registry = MeshRegistry::without_gpu();
+        let root = graph.root();
+        assert!(scene.instantiate(&mut graph, root, &mut registry).is_err());
+        assert_eq!(graph.len(), 1);
+        assert!(graph[root].children().is_empty());
+    }
+
+    #[test]
+    fn flight_paths_come_from_the_file_and_go_back_into_it() {
+        let scene: SceneFile = serde_json::from_str(r#"{ "nodes": [
+            { "name": "heli", "position": [1.0, 2.0, 3.0], "rotation": [0.1, 0.2, 0.3], "flight_path": { "time_offset": 2.5 } },
+            { "name": "terrain" } ] }"#).unwrap();
+        let mut graph = SceneGraph::new();
+        let mut registry = MeshRegistry::without_gpu();
+        let root = graph.root();
+        let loaded = scene.instantiate(&mut graph, root, &mut registry).unwrap();
+
+        let heli = graph.lookup("heli").unwrap();
+        let path = *graph[heli].components.get::<FlightPath>().unwrap();
+        assert_eq!(path.start, glm::vec3(1.0, 2.0, 3.0));
+        assert_eq!(path.time_offset, 2.5);
+        assert!(graph[graph.lookup("terrain").unwrap()].components.get::<FlightPath>().is_none());
+
+        animation::animate(&mut graph, 4.0);
+        assert_ne!(graph[heli].position, path.start);
+        let captured = SceneFile::capture(&graph, root, &loaded);
+        assert_eq!(captured.nodes[0].position, [1.0, 2.0, 3.0]);
+        let rotation = captured.nodes[0].rotation;
+        assert!(rotation.iter().zip(&[0.1, 0.2, 0.3]).all(|(a, b)| (a - b).abs() < 1e-5), "{:?}", rotation);
+        assert_eq!(captured.nodes[0].flight_path.map(|path| path.time_offset), Some(2.5));
+        assert!(captured.nodes[1].flight_path.is_none());
+    }
+
+    #[test]
+    fn saved_scenes_load_back_the_same() {
+        let scene: SceneFile = serde_json::from_str(r#"{
+            "meshes": [ { "name": "rock", "path": "rock.ply", "color": [0.5, 0.5, 0.5, 1.0] } ],
+            "nodes": [
+                { "name": "heli", "model": "helicopter.json", "position": [1.0, 2.0, 3.0],
+                  "rotation": [0.1, 0.2, 0.3], "flight_path": { "time_offset": 2.5 }, "layers": 4 },
+                { "name": "rocks", "visible": false, "scale": [2.0, 2.0, 2.0], "children": [
+                    { "mesh": "rock", "color": [1.0, 0.0, 0.0, 1.0], "reference_point": [0.0, 1.0, 0.0] } ] } ] }"#).unwrap();
+        let path = std::env::temp_dir().join("gloom_scene_file_test.json");
+        let path = path.to_str().unwrap();
+        scene.save(path).unwrap();
+        let loaded = SceneFile::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        // The entries have no PartialEq, but their JSON must come out the same
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&scene).unwrap());
+        assert_eq!(loaded.nodes[1].children[0].color, Some([1.0, 0.0, 0.0, 1.0]));
+        assert!(!loaded.nodes[1].visible);
+        assert_eq!(loaded.base_dir, std::env::temp_dir());
+    }
+
+    #[test]
+    fn primitive_meshes_are_made_without_a_file() {
+        let scene: SceneFile = serde_json::from_str(r#"{
+            "meshes": [ { "name": "ball", "primitive": { "shape": "uv_sphere", "radius": 2.0 } } ],
+            "nodes": [ { "name": "a", "mesh": "ball" }, { "name": "b", "mesh": "ball" } ] }"#).unwrap();
+        let mut graph = SceneGraph::new();
+        let mut registry = MeshRegistry::without_gpu();
+        let root = graph.root();
+        let loaded = scene.instantiate(&mut graph, root, &mut registry).unwrap();
+
+        let (a, b) = (graph.lookup("a").unwrap(), graph.lookup("b").unwrap());
+        let mesh = graph[a].mesh.clone().unwrap();
+        assert!(Rc::ptr_eq(&mesh, graph[b].mesh.as_ref().unwrap()));
+        assert!((graph[a].bounds().unwrap().sphere.radius - 2.0).abs() < 1e-5);
+        let captured = serde_json::to_value(SceneFile::capture(&graph, root, &loaded).meshes).unwrap();
+        assert_eq!(captured, serde_json::json!([
+            { "name": "ball", "color": [1.0, 1.0, 1.0, 1.0], "primitive": { "shape": "uv_sphere", "radius": 2.0, "segments": 32, "rings": 16 } },
+        ]));
+    }
+}
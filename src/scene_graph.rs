@@ -1,68 +1,256 @@
 extern crate nalgebra_glm as glm;
 
-use std::mem::ManuallyDrop;
-use std::pin::Pin;
-
-// Used to create an unholy abomination upon which you should not cast your gaze. This ended up
-// being a necessity due to wanting to keep the code written by students as "straight forward" as
-// possible. It is very very double plus ungood Rust, and intentionally leaks memory like a sieve.
-// But it works, and you're more than welcome to pretend it doesn't exist! In case you're curious
-// about how it works: It allocates memory on the heap (Box), promises to prevent it from being
-// moved or deallocated until dropped (Pin) and finally prevents the compiler from dropping it
-// automatically at all (ManuallyDrop).
-// ...
-// If that sounds like a janky solution, it's because it is!
-// Prettier, Rustier and better solutions were tried numerous times, but were all found wanting of
-// having what I arbitrarily decided to be the required level of "simplicity of use".
-pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
+use std::ops::{Index, IndexMut};
+
+use crate::bounds::{merge_optional, Bounds};
+use crate::components::Components;
+use crate::mesh_registry::MeshHandle;
+
+// The scene graph owns every node in a single arena (a Vec of slots). Nodes refer to each other
+// through NodeId handles instead of pointers, so the graph can hand out plain references, free
+// nodes when they are despawned and reuse their slots later on. Every slot carries a generation
+// counter that is bumped whenever the slot is freed, which makes a NodeId pointing at a despawned
+// node stale rather than silently aliasing whatever got allocated in its place.
+
+// The order in which Euler angle rotations are composed. XYZ means the matrix Rx * Ry * Rz,
+// so the Z rotation is applied to a vector first and the X rotation last.
+#[allow(dead_code, clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EulerOrder {
+    XYZ,
+    XZY,
+    YXZ,
+    YZX,
+    ZXY,
+    ZYX,
+}
+
+impl EulerOrder {
+    // The axis indices in the order they appear in the matrix product
+    fn axes(self) -> [usize; 3] {
+        match self {
+            EulerOrder::XYZ => [0, 1, 2],
+            EulerOrder::XZY => [0, 2, 1],
+            EulerOrder::YXZ => [1, 0, 2],
+            EulerOrder::YZX => [1, 2, 0],
+            EulerOrder::ZXY => [2, 0, 1],
+            EulerOrder::ZYX => [2, 1, 0],
+        }
+    }
+}
+
+pub fn quat_from_euler(angles: &glm::Vec3, order: EulerOrder) -> glm::Quat {
+    let axes = [glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, 1.0)];
+    order.axes().iter().fold(glm::quat_identity(), |rotation, &axis| {
+        rotation * glm::quat_angle_axis(angles[axis], &axes[axis])
+    })
+}
+
+pub fn quat_to_euler(rotation: &glm::Quat, order: EulerOrder) -> glm::Vec3 {
+    let m = glm::quat_to_mat3(rotation);
+    let [i, j, k] = order.axes();
+    // Cyclic orders (XYZ, YZX, ZXY) and the others differ in the signs of the off-diagonal terms
+    let sign = if (j + 3 - i) % 3 == 1 { 1.0 } else { -1.0 };
+
+    let mut angles: glm::Vec3 = glm::zero();
+    let sin_middle = (sign * m[(i, k)]).clamp(-1.0, 1.0);
+    // Not asin, which loses most of its precision right where the gimbal locks
+    angles[j] = sin_middle.atan2(m[(i, i)].hypot(m[(i, j)]));
+    if sin_middle.abs() < 0.9999 {
+        angles[i] = (-sign * m[(j, k)]).atan2(m[(k, k)]);
+        angles[k] = (-sign * m[(i, j)]).atan2(m[(i, i)]);
+    } else {
+        // Gimbal lock, only one of the outer angles can be recovered. Put all of it into the first.
+        angles[i] = (sign * m[(k, j)]).atan2(m[(j, j)]);
+    }
+    angles
+}
+
+// Render layers a node can belong to. A pass draws the nodes sharing at least one layer with its mask.
+pub const LAYER_DEFAULT  : u32 = 1 << 0;
+pub const LAYER_TERRAIN  : u32 = 1 << 1;
+pub const LAYER_VEHICLES : u32 = 1 << 2;
+#[allow(dead_code)]
+pub const LAYER_DEBUG    : u32 = 1 << 3;
+pub const LAYER_ALL      : u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index      : u32,
+    generation : u32,
+}
 
 pub struct SceneNode {
+    pub name            : Option<String>, // What I'm called, for looking me up by path
+
     pub position        : glm::Vec3,   // Where I should be in relation to my parent
-    pub rotation        : glm::Vec3,   // How I should be rotated, around the X, the Y and the Z axes
+    pub rotation        : glm::Quat,   // How I should be rotated
     pub scale           : glm::Vec3,   // How I should be scaled
     pub reference_point : glm::Vec3,   // The point I shall rotate and scale about
 
-    pub vao_id      : u32,             // What I should draw
-    pub index_count : i32,             // How much of it there is to draw
+    pub mesh        : Option<MeshHandle>, // What I should draw, shared with whoever else draws it
+
+    pub visible : bool,                // Whether I and everything below me should be drawn at all
+    pub layers  : u32,                 // Which render layers I'm drawn in, see LAYER_*
+
+    pub components : Components,       // Whatever else systems want to know about me
 
-    pub children: Vec<*mut SceneNode>, // Those I command
+    parent   : Option<NodeId>,         // The one I answer to
+    children : Vec<NodeId>,            // Those I command
+
+    local_matrix : glm::Mat4,          // Cached by SceneGraph::update_transforms
+    world_matrix : glm::Mat4,          // Cached by SceneGraph::update_transforms
+    dirty        : bool,               // Whether my transform changed since the caches were filled
+
+    bounds         : Option<Bounds>,   // The extent of what I draw, in my own coordinate system
+    world_bounds   : Option<Bounds>,   // My own bounds in world space
+    subtree_bounds : Option<Bounds>,   // My bounds merged with those of everything below me, in world space
 }
 
 impl SceneNode {
 
-    pub fn new() -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+    pub fn new() -> SceneNode {
+        SceneNode {
+            name            : None,
             position        : glm::zero(),
-            rotation        : glm::zero(),
+            rotation        : glm::quat_identity(),
             scale           : glm::vec3(1.0, 1.0, 1.0),
             reference_point : glm::zero(),
-            vao_id          : 0,
-            index_count     : -1,
+            mesh            : None,
+            bounds          : None,
+            visible         : true,
+            layers          : LAYER_DEFAULT,
+            components      : Components::default(),
+            parent          : None,
             children        : vec![],
-        })))
+            local_matrix    : glm::identity(),
+            world_matrix    : glm::identity(),
+            dirty           : true,
+            world_bounds    : None,
+            subtree_bounds  : None,
+        }
     }
 
-    pub fn from_vao(vao_id: u32, index_count: i32) -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
-            position        : glm::zero(),
-            rotation        : glm::zero(),
-            scale           : glm::vec3(1.0, 1.0, 1.0),
-            reference_point : glm::zero(),
-            vao_id,
-            index_count,
-            children: vec![],
-        })))
+    pub fn with_mesh(mesh: MeshHandle) -> SceneNode {
+        SceneNode {
+            bounds : mesh.bounds,
+            mesh   : Some(mesh),
+            ..SceneNode::new()
+        }
     }
 
-    pub fn add_child(&mut self, child: &SceneNode) {
-        self.children.push(child as *const SceneNode as *mut SceneNode)
+    // Gives the node a name, e.g. SceneNode::with_mesh(rotor).named("main_rotor")
+    #[allow(dead_code)]
+    pub fn named(mut self, name: &str) -> SceneNode {
+        self.name = Some(name.to_string());
+        self
     }
 
-    #[allow(dead_code)]
-    pub fn get_child(& mut self, index: usize) -> & mut SceneNode {
-        unsafe {
-            &mut (*self.children[index])
+    // The transformation from my own coordinate system into my parent's, computed from scratch.
+    // Scaling and rotation both happen about the reference point.
+    pub fn compose_local_matrix(&self) -> glm::Mat4 {
+        glm::translation(&self.position)
+            * glm::translation(&self.reference_point)
+            * glm::quat_to_mat4(&self.rotation)
+            * glm::scaling(&self.scale)
+            * glm::translation(&-self.reference_point)
+    }
+
+    // The inverse of compose_local_matrix: picks the position, rotation and scale that reproduce
+    // the given transformation, keeping the current reference point. Shear can not be represented
+    // and is lost.
+    pub fn set_local_matrix(&mut self, matrix: &glm::Mat4) {
+        let linear: glm::Mat3 = glm::mat4_to_mat3(matrix);
+        let mut scale = glm::vec3(
+            glm::length(&linear.column(0).into_owned()),
+            glm::length(&linear.column(1).into_owned()),
+            glm::length(&linear.column(2).into_owned()),
+        );
+        if linear.determinant() < 0.0 {
+            scale.x = -scale.x;
+        }
+
+        let mut rotation = linear;
+        for i in 0..3 {
+            let column = rotation.column(i) / scale[i];
+            rotation.set_column(i, &column);
         }
+        self.rotation = glm::quat_normalize(&glm::mat3_to_quat(&rotation));
+        self.scale = scale;
+
+        let translation = glm::vec3(matrix[(0, 3)], matrix[(1, 3)], matrix[(2, 3)]);
+        self.position = translation - self.reference_point + linear * self.reference_point;
+    }
+
+    // Sets the rotation from Euler angles, given in radians around the X, the Y and the Z axes
+    pub fn set_euler(&mut self, angles: &glm::Vec3, order: EulerOrder) {
+        self.rotation = quat_from_euler(angles, order);
+    }
+
+    // The rotation as Euler angles around the X, the Y and the Z axes
+    #[allow(dead_code)]
+    pub fn euler(&self, order: EulerOrder) -> glm::Vec3 {
+        quat_to_euler(&self.rotation, order)
+    }
+
+    // Transforms normals from my coordinate system into world space, without picking up the
+    // stretching that non-uniform scaling would otherwise put on them
+    pub fn normal_matrix(&self) -> glm::Mat3 {
+        glm::transpose(&glm::inverse(&glm::mat4_to_mat3(&self.world_matrix)))
+    }
+
+    // The local and world matrices as of the last SceneGraph::update_transforms
+    #[allow(dead_code)]
+    pub fn local_matrix(&self) -> &glm::Mat4 {
+        &self.local_matrix
+    }
+
+    pub fn world_matrix(&self) -> &glm::Mat4 {
+        &self.world_matrix
+    }
+
+    // The extent of what I draw, in my own coordinate system
+    #[allow(dead_code)]
+    pub fn bounds(&self) -> Option<&Bounds> {
+        self.bounds.as_ref()
+    }
+
+    // Replaces my bounds and marks me dirty, so the next update_transforms moves them into world space
+    #[allow(dead_code)]
+    pub fn set_bounds(&mut self, bounds: Option<Bounds>) {
+        self.bounds = bounds;
+        self.dirty = true;
+    }
+
+    // My bounds and those of my whole subtree, in world space, as of the last update_transforms.
+    // None if there is nothing to draw.
+    #[allow(dead_code)]
+    pub fn world_bounds(&self) -> Option<&Bounds> {
+        self.world_bounds.as_ref()
+    }
+
+    #[allow(dead_code)]
+    pub fn subtree_bounds(&self) -> Option<&Bounds> {
+        self.subtree_bounds.as_ref()
+    }
+
+    // Whether my cached matrices are out of date. Mutable access through the SceneGraph sets this
+    // automatically, so it only needs to be called by hand to force a recomputation.
+    #[allow(dead_code)]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
     }
 
     #[allow(dead_code)]
@@ -72,47 +260,659 @@ impl SceneNode {
 
     #[allow(dead_code)]
     pub fn print(&self) {
+        let euler = self.euler(EulerOrder::XYZ);
         println!(
 "SceneNode {{
+    Name:      {}
+    Mesh:      {}
     VAO:       {}
     Indices:   {}
     Children:  {}
     Position:  [{:.2}, {:.2}, {:.2}]
     Rotation:  [{:.2}, {:.2}, {:.2}]
+    Scale:     [{:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
+    Visible:   {}
+    Layers:    {:#x}
+    Components: {}
 }}",
-            self.vao_id,
-            self.index_count,
+            self.name.as_deref().unwrap_or("-"),
+            self.mesh.as_ref().map_or("-", |mesh| mesh.name.as_str()),
+            self.mesh.as_ref().map_or(0, |mesh| mesh.gpu.vao_id),
+            self.mesh.as_ref().map_or(0, |mesh| mesh.gpu.index_count),
             self.children.len(),
             self.position.x,
             self.position.y,
             self.position.z,
-            self.rotation.x,
-            self.rotation.y,
-            self.rotation.z,
+            euler.x,
+            euler.y,
+            euler.z,
+            self.scale.x,
+            self.scale.y,
+            self.scale.z,
             self.reference_point.x,
             self.reference_point.y,
             self.reference_point.z,
+            self.visible,
+            self.layers,
+            self.components.len(),
         );
     }
 
 }
 
+impl Default for SceneNode {
+    fn default() -> Self {
+        SceneNode::new()
+    }
+}
+
+
+struct Slot {
+    generation : u32,
+    node       : Option<SceneNode>,
+}
+
+pub struct SceneGraph {
+    slots : Vec<Slot>,
+    free  : Vec<u32>,   // Indices of empty slots, ready to be reused
+    root  : NodeId,
+}
 
-// You can also use square brackets to access the children of a SceneNode
-use std::ops::{Index, IndexMut};
-impl Index<usize> for SceneNode {
-    type Output = SceneNode;
-    fn index(&self, index: usize) -> &SceneNode {
-        unsafe {
-            & *(self.children[index] as *const SceneNode)
+impl SceneGraph {
+
+    // Creates a graph containing nothing but an empty root node
+    pub fn new() -> SceneGraph {
+        let mut graph = SceneGraph {
+            slots : vec![],
+            free  : vec![],
+            root  : NodeId { index: 0, generation: 0 },
+        };
+        graph.root = graph.insert(SceneNode::new());
+        graph
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    // Number of nodes currently alive in the graph, the root included
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&SceneNode> {
+        match self.slots.get(id.index as usize) {
+            Some(slot) if slot.generation == id.generation => slot.node.as_ref(),
+            _ => None,
+        }
+    }
+
+    // Handing out a mutable node marks it dirty, as the caller may be about to move it
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        let node = self.get_mut_untracked(id)?;
+        node.dirty = true;
+        Some(node)
+    }
+
+    // Every node alive in the graph, in no particular order
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &SceneNode)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.node.as_ref().map(|node| (NodeId { index: index as u32, generation: slot.generation }, node))
+        })
+    }
+
+    // Moves the node into the graph and makes it the last child of `parent`
+    pub fn add_child(&mut self, parent: NodeId, node: SceneNode) -> NodeId {
+        assert!(self.contains(parent), "Tried to add a child to a node that is not in the graph");
+        let id = self.insert(node);
+        self.attach(parent, id);
+        id
+    }
+
+    // Returns the index'th child of the node, if both of them exist
+    pub fn get_child(&self, id: NodeId, index: usize) -> Option<NodeId> {
+        self.get(id)?.children.get(index).copied()
+    }
+
+    // The first direct child of the node with the given name
+    pub fn child_by_name(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        self.get(id)?.children.iter()
+            .find(|&&child| self[child].name.as_deref() == Some(name))
+            .copied()
+    }
+
+    // Follows a path of child names separated by slashes, starting at `from`.
+    // E.g. find_path(root, "heli_3/main_rotor"). Empty segments are skipped. A segment no child is
+    // named after but that is a number picks the child at that index, like path_of writes it.
+    #[allow(dead_code)]
+    pub fn find_path(&self, from: NodeId, path: &str) -> Option<NodeId> {
+        path.split('/')
+            .filter(|segment| !segment.is_empty())
+            .try_fold(from, |current, segment| {
+                self.child_by_name(current, segment)
+                    .or_else(|| self.get_child(current, segment.parse().ok()?))
+            })
+    }
+
+    // Like find_path, but starting at the root
+    #[allow(dead_code)]
+    pub fn lookup(&self, path: &str) -> Option<NodeId> {
+        self.find_path(self.root, path)
+    }
+
+    // The slash separated path from the top of the node's hierarchy down to it, which find_path
+    // follows back. Unnamed nodes show up as their index among their siblings.
+    pub fn path_of(&self, id: NodeId) -> String {
+        let mut segments = vec![];
+        let mut current = id;
+        while let Some(parent) = self[current].parent {
+            let segment = match &self[current].name {
+                Some(name) => name.clone(),
+                None => {
+                    let index = self[parent].children.iter().position(|&c| c == current);
+                    index.expect("Children are listed by their parent").to_string()
+                }
+            };
+            segments.push(segment);
+            current = parent;
+        }
+        segments.reverse();
+        segments.join("/")
+    }
+
+    // The first node with the given name anywhere in the graph, searched depth first from the root
+    // and then through any detached hierarchies
+    #[allow(dead_code)]
+    pub fn find_by_name(&self, name: &str) -> Option<NodeId> {
+        self.find_all_by_name(name).into_iter().next()
+    }
+
+    // Every node with the given name, in the same order find_by_name searches them
+    pub fn find_all_by_name(&self, name: &str) -> Vec<NodeId> {
+        let mut found = vec![];
+        let mut stack: Vec<NodeId> = self.tops().into_iter().rev().collect();
+        while let Some(id) = stack.pop() {
+            let node = &self[id];
+            if node.name.as_deref() == Some(name) {
+                found.push(id);
+            }
+            stack.extend(node.children.iter().rev());
+        }
+        found
+    }
+
+    // Removes the node and everything below it from the graph, freeing their memory.
+    // Any NodeId pointing into the removed subtree becomes stale.
+    pub fn despawn(&mut self, id: NodeId) -> Result<(), String> {
+        self.check_movable(id)?;
+        self.detach_from_parent(id);
+
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            let slot = &mut self.slots[current.index as usize];
+            if let Some(node) = slot.node.take() {
+                stack.extend(node.children);
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(current.index);
+            }
+        }
+        Ok(())
+    }
+
+    // Composes the local matrices from the top of the hierarchy down to the node, without looking
+    // at the caches. Prefer SceneNode::world_matrix when the transforms are known to be updated.
+    pub fn compute_world_matrix(&self, id: NodeId) -> glm::Mat4 {
+        let mut matrix = self[id].compose_local_matrix();
+        let mut current = self[id].parent;
+        while let Some(parent) = current {
+            matrix = self[parent].compose_local_matrix() * matrix;
+            current = self[parent].parent;
+        }
+        matrix
+    }
+
+    // Refreshes the cached local and world matrices and world space bounds. Only dirty nodes and
+    // the subtrees below them are recomputed, everything else keeps what it had from the previous
+    // update. The subtree bounds are merged again for every node, which is cheap.
+    pub fn update_transforms(&mut self) {
+        // Detached nodes have no parent either, they are the tops of their own hierarchies
+        let mut stack: Vec<(NodeId, glm::Mat4, bool)> = self.tops().into_iter()
+            .map(|id| (id, glm::identity(), false))
+            .collect();
+        let mut visited = vec![];
+
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = self.get_mut_untracked(id).expect("Children always refer to live nodes");
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.local_matrix = node.compose_local_matrix();
+                node.world_matrix = parent_world * node.local_matrix;
+                node.world_bounds = node.bounds.map(|bounds| bounds.transformed(&node.world_matrix));
+                node.dirty = false;
+            }
+            let world = node.world_matrix;
+            stack.extend(node.children.iter().map(|&child| (child, world, changed)));
+            visited.push(id);
+        }
+
+        // Children come after their parents in the visiting order, so walking it backwards
+        // finishes every subtree before it is merged into its parent. Hidden subtrees take up no space.
+        for &id in visited.iter().rev() {
+            let node = &self[id];
+            let merged = node.children.iter()
+                .filter(|&&child| self[child].visible)
+                .fold(node.world_bounds, |merged, &child| merge_optional(merged, self[child].subtree_bounds));
+            self.get_mut_untracked(id).expect("Visited nodes are in the graph").subtree_bounds = merged;
+        }
+    }
+
+    // Whether the node would be drawn, i.e. neither it nor any of its ancestors is hidden.
+    // Detached nodes are never drawn.
+    pub fn is_visible_in_hierarchy(&self, id: NodeId) -> bool {
+        let mut current = Some(id);
+        while let Some(node_id) = current {
+            if !self[node_id].visible {
+                return false;
+            }
+            if node_id == self.root {
+                return true;
+            }
+            current = self[node_id].parent;
+        }
+        false
+    }
+
+    // Whether `ancestor` can be reached by walking up the parents of `id`
+    pub fn is_ancestor(&self, ancestor: NodeId, id: NodeId) -> bool {
+        let mut current = self.get(id).and_then(|node| node.parent);
+        while let Some(parent) = current {
+            if parent == ancestor {
+                return true;
+            }
+            current = self[parent].parent;
+        }
+        false
+    }
+
+    // Cuts the node and its subtree loose from their parent. The nodes stay alive in the graph
+    // and keep their NodeIds, but are no longer drawn until they are reparented.
+    #[allow(dead_code)]
+    pub fn detach(&mut self, id: NodeId) -> Result<(), String> {
+        self.check_movable(id)?;
+        self.detach_from_parent(id);
+        Ok(())
+    }
+
+    // Makes the node the last child of `new_parent`. With `keep_world_transform` the node's local
+    // transform is recomputed so that it stays exactly where it was in world space, e.g. a door
+    // falling off a helicopter. Otherwise it keeps its local transform and follows its new parent.
+    #[allow(dead_code)]
+    pub fn reparent(&mut self, id: NodeId, new_parent: NodeId, keep_world_transform: bool) -> Result<(), String> {
+        self.check_movable(id)?;
+        if !self.contains(new_parent) {
+            return Err(format!("{:?} is not in the graph", new_parent));
+        }
+        if id == new_parent || self.is_ancestor(id, new_parent) {
+            return Err(format!("Moving {:?} below {:?} would make it its own ancestor", id, new_parent));
         }
+
+        let world = self.compute_world_matrix(id);
+        self.detach_from_parent(id);
+        self.attach(new_parent, id);
+        if keep_world_transform {
+            let local = glm::inverse(&self.compute_world_matrix(new_parent)) * world;
+            self[id].set_local_matrix(&local);
+        }
+        Ok(())
+    }
+
+    // Takes a single node out of the graph and hands it back. Its children are moved up to its
+    // parent, keeping their world transforms, so removing a node never moves anything on screen.
+    #[allow(dead_code)]
+    pub fn remove(&mut self, id: NodeId) -> Result<SceneNode, String> {
+        self.check_movable(id)?;
+        let parent = self[id].parent;
+        for child in self[id].children.clone() {
+            match parent {
+                Some(parent) => self.reparent(child, parent, true)?,
+                None => {
+                    let world = self.compute_world_matrix(child);
+                    self.detach_from_parent(child);
+                    self[child].set_local_matrix(&world);
+                }
+            }
+        }
+        self.detach_from_parent(id);
+
+        let slot = &mut self.slots[id.index as usize];
+        let node = slot.node.take().expect("Checked above that the node exists");
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        Ok(node)
+    }
+
+    // The nodes without a parent: the root first, then the tops of any detached hierarchies
+    fn tops(&self) -> Vec<NodeId> {
+        let mut tops = vec![self.root];
+        tops.extend(self.nodes()
+            .filter(|&(id, node)| node.parent.is_none() && id != self.root)
+            .map(|(id, _)| id));
+        tops
+    }
+
+    fn check_movable(&self, id: NodeId) -> Result<(), String> {
+        if id == self.root {
+            return Err("The root node cannot be moved or removed".to_string());
+        }
+        if !self.contains(id) {
+            return Err(format!("{:?} is not in the graph", id));
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, mut node: SceneNode) -> NodeId {
+        // A node moved into the graph starts out unconnected, whatever it claimed before
+        node.parent = None;
+        node.children.clear();
+
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.node = Some(node);
+            NodeId { index, generation: slot.generation }
+        } else {
+            self.slots.push(Slot { generation: 0, node: Some(node) });
+            NodeId { index: (self.slots.len() - 1) as u32, generation: 0 }
+        }
+    }
+
+    // Changing a node's parent changes its world transform, but not the parent's
+    fn attach(&mut self, parent: NodeId, child: NodeId) {
+        self[child].parent = Some(parent);
+        self.get_mut_untracked(parent).expect("Parent is in the graph").children.push(child);
+    }
+
+    fn detach_from_parent(&mut self, id: NodeId) {
+        if let Some(parent) = self[id].parent.take() {
+            if let Some(parent_node) = self.get_mut_untracked(parent) {
+                parent_node.children.retain(|&child| child != id);
+            }
+        }
+    }
+
+    pub(crate) fn get_mut_untracked(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        match self.slots.get_mut(id.index as usize) {
+            Some(slot) if slot.generation == id.generation => slot.node.as_mut(),
+            _ => None,
+        }
+    }
+
+}
+
+impl Default for SceneGraph {
+    fn default() -> Self {
+        SceneGraph::new()
+    }
+}
+
+
+// You can also use square brackets to access the nodes of a SceneGraph
+impl Index<NodeId> for SceneGraph {
+    type Output = SceneNode;
+    fn index(&self, id: NodeId) -> &SceneNode {
+        self.get(id).expect("NodeId does not refer to a node in this graph")
+    }
+}
+impl IndexMut<NodeId> for SceneGraph {
+    fn index_mut(&mut self, id: NodeId) -> &mut SceneNode {
+        self.get_mut(id).expect("NodeId does not refer to a node in this graph")
     }
 }
-impl IndexMut<usize> for SceneNode {
-    fn index_mut(&mut self, index: usize) -> &mut SceneNode {
-        unsafe {
-            &mut (*self.children[index])
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::transform_point;
+
+    const ORDERS: [EulerOrder; 6] = [
+        EulerOrder::XYZ, EulerOrder::XZY, EulerOrder::YXZ, EulerOrder::YZX, EulerOrder::ZXY, EulerOrder::ZYX,
+    ];
+
+    fn assert_matrices_near(a: &glm::Mat4, b: &glm::Mat4) {
+        assert!((a - b).abs().max() < 1e-4, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn euler_angles_round_trip() {
+        // The middle angle of every order within a quarter turn, where the angles are unique
+        let angles = glm::vec3(0.3, -1.1, 1.2);
+        for &order in ORDERS.iter() {
+            let back = quat_to_euler(&quat_from_euler(&angles, order), order);
+            assert!(glm::distance(&back, &angles) < 1e-4, "{:?} gave {:?}", order, back);
+        }
+    }
+
+    #[test]
+    fn euler_angles_beyond_a_quarter_turn_give_the_same_rotation() {
+        let angles = glm::vec3(2.8, -2.0, 2.5);
+        for &order in ORDERS.iter() {
+            let rotation = quat_from_euler(&angles, order);
+            let back = quat_from_euler(&quat_to_euler(&rotation, order), order);
+            assert_matrices_near(&glm::quat_to_mat4(&back), &glm::quat_to_mat4(&rotation));
+        }
+    }
+
+    #[test]
+    fn euler_angles_in_gimbal_lock_give_the_same_rotation() {
+        for &order in ORDERS.iter() {
+            let mut angles = glm::vec3(0.4, 0.4, 0.4);
+            angles[order.axes()[1]] = std::f32::consts::FRAC_PI_2;
+            let rotation = quat_from_euler(&angles, order);
+            let back = quat_from_euler(&quat_to_euler(&rotation, order), order);
+            assert_matrices_near(&glm::quat_to_mat4(&back), &glm::quat_to_mat4(&rotation));
+        }
+    }
+
+    #[test]
+    fn set_local_matrix_undoes_compose_local_matrix() {
+        let mut node = SceneNode::new();
+        node.position = glm::vec3(1.0, -2.0, 3.0);
+        node.set_euler(&glm::vec3(0.2, 0.7, -1.3), EulerOrder::XYZ);
+        node.scale = glm::vec3(2.0, 0.5, 3.0);
+        node.reference_point = glm::vec3(0.5, 1.0, -0.5);
+        let matrix = node.compose_local_matrix();
+
+        let mut copy = SceneNode::new();
+        copy.reference_point = node.reference_point;
+        copy.set_local_matrix(&matrix);
+        assert_matrices_near(&copy.compose_local_matrix(), &matrix);
+        assert!(glm::distance(&copy.position, &node.position) < 1e-4);
+        assert!(glm::distance(&copy.scale, &node.scale) < 1e-4);
+    }
+
+    #[test]
+    fn set_local_matrix_keeps_mirroring() {
+        let mut node = SceneNode::new();
+        node.scale = glm::vec3(-1.0, 2.0, 1.0);
+        node.set_euler(&glm::vec3(0.0, 0.5, 0.0), EulerOrder::XYZ);
+        let matrix = node.compose_local_matrix();
+
+        let mut copy = SceneNode::new();
+        copy.set_local_matrix(&matrix);
+        assert_matrices_near(&copy.compose_local_matrix(), &matrix);
+    }
+
+    // A parent turned, scaled and moved, with a child placed somewhere below it
+    fn parent_and_child() -> (SceneGraph, NodeId, NodeId) {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let mut parent = SceneNode::new();
+        parent.position = glm::vec3(5.0, 0.0, -2.0);
+        parent.set_euler(&glm::vec3(0.0, 0.8, 0.3), EulerOrder::XYZ);
+        parent.scale = glm::vec3(2.0, 2.0, 2.0);
+        parent.reference_point = glm::vec3(1.0, 0.0, 0.0);
+        let parent = graph.add_child(root, parent);
+        let mut child = SceneNode::new();
+        child.position = glm::vec3(0.0, 1.0, 3.0);
+        child.set_euler(&glm::vec3(0.5, 0.0, 0.0), EulerOrder::XYZ);
+        let child = graph.add_child(parent, child);
+        (graph, parent, child)
+    }
+
+    #[test]
+    fn reparent_can_keep_the_world_transform() {
+        let (mut graph, parent, child) = parent_and_child();
+        let root = graph.root();
+        let world = graph.compute_world_matrix(child);
+        graph.reparent(child, root, true).unwrap();
+        assert_eq!(graph[child].parent(), Some(root));
+        assert!(graph[parent].children().is_empty());
+        assert_matrices_near(&graph.compute_world_matrix(child), &world);
+    }
+
+    #[test]
+    fn reparent_can_keep_the_local_transform() {
+        let (mut graph, _, child) = parent_and_child();
+        let root = graph.root();
+        let local = graph[child].compose_local_matrix();
+        graph.reparent(child, root, false).unwrap();
+        assert_matrices_near(&graph.compute_world_matrix(child), &local);
+    }
+
+    #[test]
+    fn reparent_refuses_cycles() {
+        let (mut graph, parent, child) = parent_and_child();
+        assert!(graph.reparent(parent, child, true).is_err());
+        assert!(graph.reparent(parent, parent, true).is_err());
+        assert!(graph.reparent(graph.root(), child, true).is_err());
+        assert_eq!(graph[child].parent(), Some(parent));
+    }
+
+    #[test]
+    fn remove_hands_the_children_up_in_place() {
+        let (mut graph, parent, child) = parent_and_child();
+        let root = graph.root();
+        let world = graph.compute_world_matrix(child);
+        graph.remove(parent).unwrap();
+        assert!(!graph.contains(parent));
+        assert_eq!(graph[root].children(), &[child]);
+        assert_matrices_near(&graph.compute_world_matrix(child), &world);
+    }
+
+    #[test]
+    fn remove_from_a_detached_hierarchy_keeps_the_world_transform() {
+        let (mut graph, parent, child) = parent_and_child();
+        graph.detach(parent).unwrap();
+        let world = graph.compute_world_matrix(child);
+        graph.remove(parent).unwrap();
+        assert_eq!(graph[child].parent(), None);
+        assert_matrices_near(&graph.compute_world_matrix(child), &world);
+    }
+
+    #[test]
+    fn update_transforms_follows_dirty_parents_and_leaves_clean_subtrees_alone() {
+        let (mut graph, parent, child) = parent_and_child();
+        let root = graph.root();
+        let sibling = graph.add_child(root, SceneNode::new());
+        let unit = Bounds::from_points(&[-1.0, -1.0, -1.0, 1.0, 1.0, 1.0]);
+        graph[child].set_bounds(unit);
+        graph[sibling].set_bounds(unit);
+        graph.update_transforms();
+        assert!(!graph[child].is_dirty() && !graph[sibling].is_dirty());
+
+        // Scribble over the sibling's caches. Nothing marks it dirty, so they should survive.
+        let marker = glm::translation(&glm::vec3(0.0, 42.0, 0.0));
+        graph.get_mut_untracked(sibling).unwrap().world_matrix = marker;
+
+        graph[parent].position = glm::vec3(-3.0, 7.0, 1.0);
+        assert!(graph[parent].is_dirty() && !graph[child].is_dirty());
+        graph.update_transforms();
+        let world = graph.compute_world_matrix(child);
+        assert_matrices_near(graph[child].world_matrix(), &world);
+        let center = graph[child].world_bounds().unwrap().aabb.center();
+        assert!(glm::distance(&center, &transform_point(&world, &glm::zero())) < 1e-5);
+        assert!(graph[root].subtree_bounds().unwrap().aabb.contains(&center));
+        assert_eq!(graph[sibling].world_matrix(), &marker);
+        assert!(!graph[parent].is_dirty() && !graph[child].is_dirty());
+    }
+
+    #[test]
+    fn set_bounds_moves_the_new_bounds_into_world_space() {
+        let (mut graph, _, child) = parent_and_child();
+        graph.update_transforms();
+        assert!(graph[child].world_bounds().is_none());
+
+        graph[child].set_bounds(Bounds::from_points(&[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]));
+        graph.update_transforms();
+        let expected = graph[child].bounds().unwrap().transformed(graph[child].world_matrix());
+        let world_bounds = graph[child].world_bounds().unwrap();
+        assert!(glm::distance(&world_bounds.aabb.min, &expected.aabb.min) < 1e-5);
+        assert!(glm::distance(&world_bounds.aabb.max, &expected.aabb.max) < 1e-5);
+    }
+
+    #[test]
+    fn find_path_follows_path_of() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let heli = graph.add_child(root, SceneNode::new().named("heli"));
+        graph.add_child(heli, SceneNode::new().named("door"));
+        let unnamed = graph.add_child(heli, SceneNode::new());
+        let rotor = graph.add_child(unnamed, SceneNode::new().named("rotor"));
+
+        assert_eq!(graph.path_of(rotor), "heli/1/rotor");
+        assert_eq!(graph.lookup("heli/1/rotor"), Some(rotor));
+        assert_eq!(graph.lookup("heli/2"), None);
+    }
+
+    #[test]
+    fn find_path_prefers_names_over_indices() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        graph.add_child(root, SceneNode::new());
+        let named = graph.add_child(root, SceneNode::new().named("0"));
+        assert_eq!(graph.lookup("0"), Some(named));
+    }
+
+    #[test]
+    fn despawned_ids_go_stale_and_their_slots_are_reused() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let heli = graph.add_child(root, SceneNode::new().named("heli"));
+        let rotor = graph.add_child(heli, SceneNode::new().named("rotor"));
+        graph.despawn(heli).unwrap();
+
+        for &id in [heli, rotor].iter() {
+            assert!(!graph.contains(id));
+            assert!(graph.get(id).is_none());
+            assert!(graph.get_mut(id).is_none());
         }
+        assert_eq!(graph.lookup("heli"), None);
+        assert_eq!(graph.lookup("heli/rotor"), None);
+        assert!(graph[root].children().is_empty());
+        assert_eq!(graph.len(), 1);
+
+        // The freed slots are handed out again, under a new generation
+        let tail = graph.add_child(root, SceneNode::new().named("tail"));
+        assert!([heli.index, rotor.index].contains(&tail.index));
+        let old = if tail.index == heli.index { heli } else { rotor };
+        assert_eq!(tail.generation, old.generation + 1);
+        assert!(graph.get(old).is_none());
+        assert_eq!(graph.lookup("tail"), Some(tail));
+    }
+
+    #[test]
+    #[should_panic(expected = "NodeId does not refer to a node in this graph")]
+    fn indexing_with_a_despawned_id_panics() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let heli = graph.add_child(root, SceneNode::new());
+        graph.despawn(heli).unwrap();
+        graph.add_child(root, SceneNode::new());
+        graph[heli].visible = false;
     }
 }
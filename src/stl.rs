@@ -0,0 +1,212 @@
+extern crate nalgebra_glm as glm;
+
+use std::io::Write;
+
+use crate::mesh::Mesh;
+
+// Reading and writing STL files, ASCII as well as binary. STL stores every triangle on its own with
+// a face normal, so the meshes read have three vertices per triangle, all facing the way the
+// triangle does, and no colours of their own (they come out white).
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+pub fn load_stl(path: &str) -> Result<Mesh, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    read_stl(&data).map_err(|e| format!("Failed to load {}: {}", path, e))
+}
+
+pub fn read_stl(data: &[u8]) -> Result<Mesh, String> {
+    // Binary files may start with "solid" too, so their size is the better tell
+    let binary_size = data.get(80..84)
+        .map(|count| 84 + 50 * u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize);
+    let triangles = if binary_size == Some(data.len()) || !data.starts_with(b"solid") {
+        read_binary(data)?
+    } else {
+        read_ascii(data)?
+    };
+
+    let mut mesh = Mesh::empty();
+    for (normal, corners) in triangles {
+        let normal = if glm::length(&normal) > 0.0 { glm::normalize(&normal) } else { face_normal(&corners) };
+        for corner in &corners {
+            mesh.indices.push((mesh.vertices.len() / 3) as u32);
+            mesh.vertices.extend(corner.iter());
+            mesh.normals.extend(normal.iter());
+            mesh.colors.extend([1.0, 1.0, 1.0, 1.0]);
+        }
+    }
+    mesh.index_count = mesh.indices.len() as i32;
+    Ok(mesh)
+}
+
+type Triangle = (glm::Vec3, [glm::Vec3; 3]); // Normal and corners
+
+fn read_binary(data: &[u8]) -> Result<Vec<Triangle>, String> {
+    if data.len() < 84 {
+        return Err(String::from("Too short for a binary STL file"));
+    }
+    let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+    if data.len() < 84 + 50 * count {
+        return Err(format!("Expected {} triangles, but the file ends early", count));
+    }
+    let float = |at: usize| f32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    let vector = |at: usize| glm::vec3(float(at), float(at + 4), float(at + 8));
+    Ok((0..count)
+        .map(|i| {
+            let at = 84 + 50 * i;   // Normal, three corners and two bytes of attributes
+            (vector(at), [vector(at + 12), vector(at + 24), vector(at + 36)])
+        })
+        .collect())
+}
+
+fn read_ascii(data: &[u8]) -> Result<Vec<Triangle>, String> {
+    let text = std::str::from_utf8(data).map_err(|_| "The ASCII STL file is not text")?;
+    let mut triangles = vec![];
+    let mut normal = glm::zero();
+    let mut corners = vec![];
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let vector = |words: &[&str]| -> Result<glm::Vec3, String> {
+            let numbers = words.iter()
+                .map(|word| word.parse::<f32>().map_err(|_| format!("Invalid number {} in STL file", word)))
+                .collect::<Result<Vec<f32>, String>>()?;
+            match numbers.as_slice() {
+                [x, y, z] => Ok(glm::vec3(*x, *y, *z)),
+                _ => Err(format!("Expected three numbers in STL line: {}", line.trim())),
+            }
+        };
+        match words.as_slice() {
+            ["facet", "normal", rest @ ..] => {
+                normal = vector(rest)?;
+                corners.clear();
+            },
+            ["vertex", rest @ ..] => corners.push(vector(rest)?),
+            ["endfacet"] => {
+                match corners.as_slice() {
+                    [a, b, c] => triangles.push((normal, [*a, *b, *c])),
+                    _ => return Err(format!("STL facet with {} corners", corners.len())),
+                }
+            },
+            _ => {} // solid, outer loop, endloop and endsolid
+        }
+    }
+    Ok(triangles)
+}
+
+fn face_normal(corners: &[glm::Vec3; 3]) -> glm::Vec3 {
+    let normal = glm::cross(&(corners[1] - corners[0]), &(corners[2] - corners[0]));
+    if glm::length(&normal) > 0.0 { glm::normalize(&normal) } else { normal }
+}
+
+#[allow(dead_code)]
+pub fn save_stl(mesh: &Mesh, path: &str, format: StlFormat) -> Result<(), String> {
+    let mut data = vec![];
+    write_stl(mesh, &mut data, format).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    std::fs::write(path, data).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+// The triangles with normals computed from their corners. Colours and vertex normals are lost.
+#[allow(dead_code)]
+pub fn write_stl<W: Write>(mesh: &Mesh, out: &mut W, format: StlFormat) -> std::io::Result<()> {
+    let vertex = |i: u32| glm::make_vec3(&mesh.vertices[3 * i as usize..3 * i as usize + 3]);
+    let triangles = mesh.indices.chunks_exact(3).map(|ids| {
+        let corners = [vertex(ids[0]), vertex(ids[1]), vertex(ids[2])];
+        (face_normal(&corners), corners)
+    });
+
+    match format {
+        StlFormat::Ascii => {
+            writeln!(out, "solid gloom")?;
+            for (normal, corners) in triangles {
+                writeln!(out, "  facet normal {} {} {}", normal.x, normal.y, normal.z)?;
+                writeln!(out, "    outer loop")?;
+                for corner in &corners {
+                    writeln!(out, "      vertex {} {} {}", corner.x, corner.y, corner.z)?;
+                }
+                writeln!(out, "    endloop")?;
+                writeln!(out, "  endfacet")?;
+            }
+            writeln!(out, "endsolid gloom")?;
+        },
+        StlFormat::Binary => {
+            let mut header = [b' '; 80];
+            let text = b"Written by gloom-rs";
+            header[..text.len()].copy_from_slice(text);
+            out.write_all(&header)?;
+            out.write_all(&((mesh.indices.len() / 3) as u32).to_le_bytes())?;
+            for (normal, corners) in triangles {
+                for vector in std::iter::once(&normal).chain(corners.iter()) {
+                    for x in vector.iter() {
+                        out.write_all(&x.to_le_bytes())?;
+                    }
+                }
+                out.write_all(&[0, 0])?;
+            }
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two triangles sharing an edge, one flat on the ground and one standing up
+    fn fold() -> Mesh {
+        let mut mesh = Mesh::empty();
+        mesh.vertices = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, 1.0, 0.0];
+        mesh.indices = vec![0, 1, 2, 0, 3, 1];
+        mesh.index_count = 6;
+        mesh
+    }
+
+    #[test]
+    fn round_trips_in_both_formats() {
+        for &format in [StlFormat::Ascii, StlFormat::Binary].iter() {
+            let mut data = vec![];
+            write_stl(&fold(), &mut data, format).unwrap();
+            let read = read_stl(&data).unwrap_or_else(|e| panic!("{:?}: {}", format, e));
+            // Every corner gets a vertex of its own
+            assert_eq!(read.vertices, [
+                0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, -1.0,
+                0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0,
+            ], "{:?}", format);
+            assert_eq!(read.indices, [0, 1, 2, 3, 4, 5]);
+            assert_eq!(read.normals, [0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, -1.0, 0.0, 0.0, -1.0]);
+            assert_eq!(read.colors, [1.0; 24]);
+        }
+    }
+
+    #[test]
+    fn binary_files_may_start_with_solid() {
+        let mut data = vec![];
+        write_stl(&fold(), &mut data, StlFormat::Binary).unwrap();
+        data[..5].copy_from_slice(b"solid");
+        assert_eq!(read_stl(&data).unwrap().indices.len(), 6);
+    }
+
+    #[test]
+    fn missing_normals_are_computed() {
+        let data = "solid test\nfacet normal 0 0 0\nouter loop\nvertex 0 0 0\nvertex 0 0 1\nvertex 1 0 0\n\
+            endloop\nendfacet\nendsolid test\n";
+        assert_eq!(read_stl(data.as_bytes()).unwrap().normals[..3], [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn broken_files_are_errors() {
+        let two_corners = "solid test\nfacet normal 0 1 0\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nendloop\nendfacet\n";
+        assert!(read_stl(two_corners.as_bytes()).is_err());
+        let bad_number = "solid test\nfacet normal 0 1 0\nouter loop\nvertex 0 zero 0\n";
+        assert!(read_stl(bad_number.as_bytes()).is_err());
+
+        let mut truncated = vec![];
+        write_stl(&fold(), &mut truncated, StlFormat::Binary).unwrap();
+        truncated.truncate(100);
+        assert!(read_stl(&truncated).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use crate::mesh::Mesh;
+
+// Tangent space for normal mapping, following the conventions of MikkTSpace so that normal maps
+// baked by Blender, Substance and the like come out right: every tangent is perpendicular to its
+// vertex normal, the fourth component is the sign of the bitangent, and the bitangent is
+// sign * cross(normal, tangent). The tangents of the triangles around a vertex are averaged,
+// weighted by the angle each triangle has there. Where the texture is mirrored, the triangles on
+// either side disagree about the sign, and the vertex is split in two.
+
+impl Mesh {
+
+    // Generates tangents if the mesh has texture coordinates but no tangents yet
+    pub fn ensure_tangents(&mut self) {
+        let vertex_count = self.vertices.len() / 3;
+        if self.uvs.len() == vertex_count * 2 && self.tangents.len() != vertex_count * 4 {
+            self.generate_tangents();
+        }
+    }
+
+    // Replaces the tangents with ones made from the positions, normals and texture coordinates.
+    // Without texture coordinates there is nothing to make them from, and the mesh is left without.
+    pub fn generate_tangents(&mut self) {
+        self.tangents.clear();
+        let vertex_count = self.vertices.len() / 3;
+        if self.uvs.len() != vertex_count * 2 {
+            return;
+        }
+        self.ensure_normals();
+
+        let position = |i: u32| glm::make_vec3(&self.vertices[3 * i as usize..3 * i as usize + 3]);
+        let normal = |i: u32| glm::make_vec3(&self.normals[3 * i as usize..3 * i as usize + 3]);
+        let uv = |i: u32| glm::make_vec2(&self.uvs[2 * i as usize..2 * i as usize + 2]);
+
+        // The tangent each corner contributes to its vertex, how much it counts and its sign
+        let mut corners = Vec::with_capacity(self.indices.len());
+        for t in self.indices.chunks_exact(3) {
+            let (p, w) = ([position(t[0]), position(t[1]), position(t[2])], [uv(t[0]), uv(t[1]), uv(t[2])]);
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            let (d1, d2) = (w[1] - w[0], w[2] - w[0]);
+            let area = d1.x * d2.y - d2.x * d1.y;
+            // Triangles with no area in the texture have no direction in it either
+            let (tangent, bitangent) = if area.abs() > f32::EPSILON {
+                ((e1 * d2.y - e2 * d1.y) / area, (e2 * d1.x - e1 * d2.x) / area)
+            } else {
+                (glm::Vec3::zeros(), glm::Vec3::zeros())
+            };
+
+            for corner in 0..3 {
+                let n = normal(t[corner]);
+                let flat = tangent - n * glm::dot(&n, &tangent);
+                let to_next = p[(corner + 1) % 3] - p[corner];
+                let to_previous = p[(corner + 2) % 3] - p[corner];
+                let angle = if glm::length(&to_next) > 0.0 && glm::length(&to_previous) > 0.0 {
+                    glm::angle(&to_next, &to_previous)
+                } else {
+                    0.0
+                };
+                let sign = if glm::dot(&glm::cross(&n, &tangent), &bitangent) < 0.0 { -1.0 } else { 1.0 };
+                let flat = if glm::length(&flat) > 0.0 { glm::normalize(&flat) } else { flat };
+                corners.push((flat * angle, sign));
+            }
+        }
+
+        // Sum up per vertex and sign, giving the vertex to the first sign seen and copies to the other
+        let mut sums: Vec<(glm::Vec3, f32)> = vec![(glm::Vec3::zeros(), 1.0); vertex_count];
+        let mut first_sign: Vec<Option<f32>> = vec![None; vertex_count];
+        let mut copies: HashMap<u32, u32> = HashMap::new();
+        for (corner, &(tangent, sign)) in corners.iter().enumerate() {
+            let vertex = self.indices[corner];
+            let target = match first_sign[vertex as usize] {
+                None => {
+                    first_sign[vertex as usize] = Some(sign);
+                    sums[vertex as usize].1 = sign;
+                    vertex
+                },
+                Some(first) if first == sign => vertex,
+                Some(_) => match copies.get(&vertex) {
+                    Some(&copy) => copy,
+                    None => {
+                        let copy = self.duplicate_vertex(vertex);
+                        sums.push((glm::Vec3::zeros(), sign));
+                        copies.insert(vertex, copy);
+                        copy
+                    },
+                },
+            };
+            self.indices[corner] = target;
+            sums[target as usize].0 += tangent;
+        }
+
+        let normal = |i: usize| glm::make_vec3(&self.normals[3 * i..3 * i + 3]);
+        self.tangents = sums.iter().enumerate()
+            .flat_map(|(i, &(sum, sign))| {
+                let n = normal(i);
+                let flat = sum - n * glm::dot(&n, &sum);
+                let t = if glm::length(&flat) > 1e-6 { glm::normalize(&flat) } else { any_perpendicular(&n) };
+                [t.x, t.y, t.z, sign]
+            })
+            .collect();
+        self.index_count = self.indices.len() as i32;
+    }
+
+}
+
+// Some direction at a right angle to `n`, for vertices the texture gives no direction at
+fn any_perpendicular(n: &glm::Vec3) -> glm::Vec3 {
+    let axis = if n.x.abs() < 0.9 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+    let t = glm::cross(n, &axis);
+    if glm::length(&t) > 0.0 { glm::normalize(&t) } else { axis }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quads side by side in the XY plane facing +Z, with corners at x = 0, 1, 2... and y = 0 and 1.
+    // `us` gives u at each x, and v runs up along Y.
+    fn strip(us: &[f32]) -> Mesh {
+        let mut mesh = Mesh::empty();
+        for (x, &u) in us.iter().enumerate() {
+            mesh.vertices.extend([x as f32, 0.0, 0.0, x as f32, 1.0, 0.0]);
+            mesh.normals.extend([0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+            mesh.uvs.extend([u, 0.0, u, 1.0]);
+        }
+        for quad in 0..us.len() as u32 - 1 {
+            let (a, b, c, d) = (2 * quad, 2 * quad + 2, 2 * quad + 3, 2 * quad + 1);
+            mesh.indices.extend([a, b, c, a, c, d]);
+        }
+        mesh.colors = vec![1.0; mesh.vertices.len() / 3 * 4];
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh
+    }
+
+    fn tangent(mesh: &Mesh, vertex: u32) -> glm::Vec4 {
+        glm::make_vec4(&mesh.tangents[4 * vertex as usize..4 * vertex as usize + 4])
+    }
+
+    #[test]
+    fn tangents_follow_u_across_the_surface() {
+        let mut quad = strip(&[0.0, 1.0]);
+        quad.ensure_tangents();
+        assert_eq!(quad.tangents.len(), 16);
+        for vertex in 0..4 {
+            assert!(glm::distance(&tangent(&quad, vertex), &glm::vec4(1.0, 0.0, 0.0, 1.0)) < 1e-6);
+        }
+    }
+
+    #[test]
+    fn mirrored_texture_splits_the_vertices_on_the_mirror() {
+        let mut mirrored = strip(&[0.0, 1.0, 0.0]);
+        mirrored.generate_tangents();
+        // The two vertices on the line down the middle get a copy each, for the other side
+        assert_eq!(mirrored.vertices.len() / 3, 8);
+        assert_eq!((mirrored.normals.len(), mirrored.uvs.len(), mirrored.colors.len()), (24, 16, 32));
+        assert_eq!(mirrored.tangents.len(), 32);
+
+        for triangle in mirrored.indices.chunks_exact(3) {
+            let right_half = triangle.iter().any(|&vertex| mirrored.vertices[3 * vertex as usize] > 1.5);
+            let expected = if right_half { glm::vec4(-1.0, 0.0, 0.0, -1.0) } else { glm::vec4(1.0, 0.0, 0.0, 1.0) };
+            for &vertex in triangle {
+                assert!(glm::distance(&tangent(&mirrored, vertex), &expected) < 1e-6, "{}", vertex);
+            }
+        }
+    }
+}
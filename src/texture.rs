@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+// Images on the GPU for the shaders to sample, read with the image crate. They are flipped on the
+// way up, so that a texture coordinate of (0, 0) is the bottom left corner of the image as OBJ files
+// and OpenGL have it.
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+// How a texture is sampled
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerSettings {
+    pub wrap       : Wrap,
+    pub min_filter : Filter,        // For texels smaller than a pixel
+    pub mag_filter : Filter,        // For texels larger than a pixel
+    pub mipmaps    : bool,          // Generate mipmaps and blend between them when minifying
+}
+
+impl Default for SamplerSettings {
+    // Tiling, trilinear
+    fn default() -> Self {
+        SamplerSettings { wrap: Wrap::Repeat, min_filter: Filter::Linear, mag_filter: Filter::Linear, mipmaps: true }
+    }
+}
+
+impl Wrap {
+    fn gl(self) -> i32 {
+        (match self {
+            Wrap::Repeat         => gl::REPEAT,
+            Wrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+            Wrap::ClampToEdge    => gl::CLAMP_TO_EDGE,
+        }) as i32
+    }
+}
+
+impl SamplerSettings {
+    fn gl_min_filter(&self) -> i32 {
+        (match (self.min_filter, self.mipmaps) {
+            (Filter::Nearest, false) => gl::NEAREST,
+            (Filter::Linear, false)  => gl::LINEAR,
+            (Filter::Nearest, true)  => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Linear, true)   => gl::LINEAR_MIPMAP_LINEAR,
+        }) as i32
+    }
+
+    fn gl_mag_filter(&self) -> i32 {
+        (match self.mag_filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear  => gl::LINEAR,
+        }) as i32
+    }
+}
+
+// An RGBA texture. The OpenGL texture is deleted along with this.
+pub struct Texture2D {
+    pub id     : u32,
+    #[allow(dead_code)]
+    pub width  : u32,
+    #[allow(dead_code)]
+    pub height : u32,
+}
+
+impl Texture2D {
+
+    // Must be called from the thread owning the OpenGL context
+    pub unsafe fn load(path: &str, settings: SamplerSettings) -> Result<Texture2D, String> {
+        let image = image::open(path).map_err(|e| format!("Failed to load texture {}: {}", path, e))?;
+        Ok(Texture2D::from_image(&image, settings))
+    }
+
+    pub unsafe fn from_image(image: &image::DynamicImage, settings: SamplerSettings) -> Texture2D {
+        let pixels = image.flipv().into_rgba8();
+        let (width, height) = pixels.dimensions();
+
+        let mut id: u32 = 0;
+        gl::GenTextures(1, &mut id);
+        gl::BindTexture(gl::TEXTURE_2D, id);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA8 as i32,
+            width as i32,
+            height as i32,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const std::os::raw::c_void,
+        );
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, settings.wrap.gl());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, settings.wrap.gl());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, settings.gl_min_filter());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, settings.gl_mag_filter());
+        if settings.mipmaps {
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+        }
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        Texture2D { id, width, height }
+    }
+
+    // Binds the texture to texture unit `unit`, which is what the shader's sampler has to be set to
+    pub unsafe fn bind(&self, unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(gl::TEXTURE_2D, self.id);
+    }
+
+}
+
+impl Drop for Texture2D {
+    fn drop(&mut self) {
+        if self.id != 0 {
+            unsafe { gl::DeleteTextures(1, &self.id) };
+        }
+    }
+}
+
+// Loads every image file only once for as long as something uses it, like the MeshRegistry does
+// for meshes. All of the textures are sampled the same way.
+pub struct TextureCache {
+    entries  : HashMap<String, Weak<Texture2D>>,
+    settings : SamplerSettings,
+}
+
+impl TextureCache {
+
+    pub fn new(settings: SamplerSettings) -> TextureCache {
+        TextureCache { entries: HashMap::new(), settings }
+    }
+
+    // Must be called from the thread owning the OpenGL context
+    pub unsafe fn load(&mut self, path: &str) -> Result<Rc<Texture2D>, String> {
+        if let Some(texture) = self.entries.get(path).and_then(Weak::upgrade) {
+            return Ok(texture);
+        }
+        self.entries.retain(|_, texture| texture.strong_count() > 0);
+        let texture = Rc::new(Texture2D::load(path, self.settings)?);
+        self.entries.insert(path.to_string(), Rc::downgrade(&texture));
+        Ok(texture)
+    }
+
+}
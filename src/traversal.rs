@@ -0,0 +1,320 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::scene_graph::{NodeId, SceneGraph, SceneNode};
+
+// Ways to walk a SceneGraph without touching OpenGL. The iterators compose the world matrices as
+// they go, from the transform fields themselves, so they are correct even when the caches filled
+// by SceneGraph::update_transforms are stale.
+
+#[allow(dead_code)]
+pub struct Visit<'a> {
+    pub id    : NodeId,
+    pub node  : &'a SceneNode,
+    pub world : glm::Mat4,      // My transformation into world space
+    pub depth : usize,          // How far below the node the traversal started at I am
+}
+
+pub struct DepthFirst<'a> {
+    graph : &'a SceneGraph,
+    stack : Vec<(NodeId, glm::Mat4, usize)>, // Node, its parent's world matrix and its depth
+}
+
+pub struct BreadthFirst<'a> {
+    graph : &'a SceneGraph,
+    queue : VecDeque<(NodeId, glm::Mat4, usize)>,
+}
+
+impl<'a> Iterator for DepthFirst<'a> {
+    type Item = Visit<'a>;
+    fn next(&mut self) -> Option<Visit<'a>> {
+        let (id, parent_world, depth) = self.stack.pop()?;
+        let node = &self.graph[id];
+        let world = parent_world * node.compose_local_matrix();
+        // Reversed, so the first child is the next one popped
+        self.stack.extend(node.children().iter().rev().map(|&child| (child, world, depth + 1)));
+        Some(Visit { id, node, world, depth })
+    }
+}
+
+impl<'a> Iterator for BreadthFirst<'a> {
+    type Item = Visit<'a>;
+    fn next(&mut self) -> Option<Visit<'a>> {
+        let (id, parent_world, depth) = self.queue.pop_front()?;
+        let node = &self.graph[id];
+        let world = parent_world * node.compose_local_matrix();
+        self.queue.extend(node.children().iter().map(|&child| (child, world, depth + 1)));
+        Some(Visit { id, node, world, depth })
+    }
+}
+
+// Something that wants to look at, and possibly change, every node of a subtree.
+// Driven by SceneGraph::walk_mut.
+pub trait SceneVisitor {
+    // Called before any of the node's children. `world` is the node's world matrix as it was when
+    // the node was reached. Returning false skips the children.
+    fn visit(&mut self, id: NodeId, node: &mut SceneNode, world: &glm::Mat4, depth: usize) -> bool;
+
+    // Called after all of the node's children have been visited
+    fn leave(&mut self, _id: NodeId, _node: &SceneNode, _depth: usize) {}
+}
+
+impl SceneGraph {
+
+    // Every node of the subtree below `from`, parents before their children, siblings in order
+    #[allow(dead_code)]
+    pub fn depth_first(&self, from: NodeId) -> DepthFirst<'_> {
+        DepthFirst {
+            graph : self,
+            stack : vec![(from, self.parent_world_matrix(from), 0)],
+        }
+    }
+
+    // Every node of the subtree below `from`, one level at a time
+    #[allow(dead_code)]
+    pub fn breadth_first(&self, from: NodeId) -> BreadthFirst<'_> {
+        BreadthFirst {
+            graph : self,
+            queue : VecDeque::from(vec![(from, self.parent_world_matrix(from), 0)]),
+        }
+    }
+
+    // Runs the visitor over the subtree below `from`, depth first. Nodes whose transform the
+    // visitor changes are marked dirty, and their children are handed the updated world matrix.
+    // A node given another mesh takes on its bounds, unless the visitor set other bounds itself.
+    #[allow(dead_code)]
+    pub fn walk_mut<V: SceneVisitor>(&mut self, from: NodeId, visitor: &mut V) {
+        let parent_world = self.parent_world_matrix(from);
+        self.walk_node(from, &parent_world, 0, visitor);
+    }
+
+    fn walk_node<V: SceneVisitor>(&mut self, id: NodeId, parent_world: &glm::Mat4, depth: usize, visitor: &mut V) {
+        let node = &self[id];
+        let before = (node.position, node.rotation, node.scale, node.reference_point);
+        let (mesh_before, bounds_before) = (node.mesh.clone(), node.bounds().copied());
+        let world = parent_world * node.compose_local_matrix();
+
+        let node = self.get_mut_untracked(id).expect("Visited nodes are in the graph");
+        let descend = visitor.visit(id, node, &world, depth);
+        let same_mesh = match (&mesh_before, &node.mesh) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        if !same_mesh && node.bounds().copied() == bounds_before {
+            // set_bounds marks the node dirty, so its world bounds follow at the next update
+            let bounds = node.mesh.as_ref().and_then(|mesh| mesh.bounds);
+            node.set_bounds(bounds);
+        }
+        let world = if before != (node.position, node.rotation, node.scale, node.reference_point) {
+            node.mark_dirty();
+            parent_world * node.compose_local_matrix()
+        } else {
+            world
+        };
+
+        if descend {
+            for child in self[id].children().to_vec() {
+                self.walk_node(child, &world, depth + 1, visitor);
+            }
+        }
+        visitor.leave(id, &self[id], depth);
+    }
+
+    fn parent_world_matrix(&self, id: NodeId) -> glm::Mat4 {
+        match self[id].parent() {
+            Some(parent) => self.compute_world_matrix(parent),
+            None => glm::identity(),
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::Bounds;
+    use crate::mesh::Mesh;
+    use crate::mesh_registry::{MeshHandle, MeshRegistry};
+
+    // root
+    // ├── a        at (1, 0, 0)
+    // │   ├── a1   at (0, 1, 0)
+    // │   └── a2   scaled by 2
+    // │       └── a2x at (0, 0, 1)
+    // └── b        turned a quarter about Y
+    //     └── b1   at (1, 0, 0)
+    fn tree() -> SceneGraph {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let mut a = SceneNode::new().named("a");
+        a.position = glm::vec3(1.0, 0.0, 0.0);
+        let a = graph.add_child(root, a);
+        let mut a1 = SceneNode::new().named("a1");
+        a1.position = glm::vec3(0.0, 1.0, 0.0);
+        graph.add_child(a, a1);
+        let mut a2 = SceneNode::new().named("a2");
+        a2.scale = glm::vec3(2.0, 2.0, 2.0);
+        let a2 = graph.add_child(a, a2);
+        let mut a2x = SceneNode::new().named("a2x");
+        a2x.position = glm::vec3(0.0, 0.0, 1.0);
+        graph.add_child(a2, a2x);
+        let mut b = SceneNode::new().named("b");
+        b.rotation = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 1.0, 0.0));
+        let b = graph.add_child(root, b);
+        let mut b1 = SceneNode::new().named("b1");
+        b1.position = glm::vec3(1.0, 0.0, 0.0);
+        graph.add_child(b, b1);
+        graph
+    }
+
+    fn names<'a>(visits: impl Iterator<Item = Visit<'a>>) -> Vec<(String, usize)> {
+        visits.map(|visit| (visit.node.name.clone().unwrap_or_default(), visit.depth)).collect()
+    }
+
+    fn origin(world: &glm::Mat4) -> glm::Vec3 {
+        world.column(3).xyz()
+    }
+
+    fn assert_near(a: glm::Vec3, b: glm::Vec3) {
+        assert!(glm::distance(&a, &b) < 1e-5, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn depth_first_goes_down_before_across() {
+        let graph = tree();
+        let order = names(graph.depth_first(graph.root()));
+        let expected = [("", 0), ("a", 1), ("a1", 2), ("a2", 2), ("a2x", 3), ("b", 1), ("b1", 2)];
+        assert_eq!(order, expected.iter().map(|&(n, d)| (n.to_string(), d)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn breadth_first_goes_across_before_down() {
+        let graph = tree();
+        let order = names(graph.breadth_first(graph.root()));
+        let expected = [("", 0), ("a", 1), ("b", 1), ("a1", 2), ("a2", 2), ("b1", 2), ("a2x", 3)];
+        assert_eq!(order, expected.iter().map(|&(n, d)| (n.to_string(), d)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn world_matrices_include_every_ancestor() {
+        let graph = tree();
+        for visit in graph.depth_first(graph.root()) {
+            assert_near(origin(&visit.world), origin(&graph.compute_world_matrix(visit.id)));
+        }
+        let world = |name: &str| {
+            let visit = graph.breadth_first(graph.root()).find(|visit| visit.node.name.as_deref() == Some(name));
+            origin(&visit.unwrap().world)
+        };
+        assert_near(world("a1"), glm::vec3(1.0, 1.0, 0.0));
+        assert_near(world("a2x"), glm::vec3(1.0, 0.0, 2.0));
+        assert_near(world("b1"), glm::vec3(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn traversal_below_a_node_starts_from_its_place_in_the_world() {
+        let graph = tree();
+        let a2 = graph.lookup("a/a2").unwrap();
+        assert_eq!(names(graph.depth_first(a2)), vec![("a2".to_string(), 0), ("a2x".to_string(), 1)]);
+        let a2x = graph.depth_first(a2).nth(1).unwrap();
+        assert_near(origin(&a2x.world), glm::vec3(1.0, 0.0, 2.0));
+    }
+
+    // Moves "a" up by one, records what it sees and stops at "a2"
+    #[derive(Default)]
+    struct Mover {
+        visited   : Vec<String>,
+        left      : Vec<String>,
+        a1_origin : Option<glm::Vec3>,
+    }
+
+    impl SceneVisitor for Mover {
+        fn visit(&mut self, _id: NodeId, node: &mut SceneNode, world: &glm::Mat4, _depth: usize) -> bool {
+            let name = node.name.clone().unwrap_or_default();
+            match name.as_str() {
+                "a" => node.position.y += 1.0,
+                "a1" => self.a1_origin = Some(origin(world)),
+                _ => {},
+            }
+            self.visited.push(name.clone());
+            name != "a2"
+        }
+
+        fn leave(&mut self, _id: NodeId, node: &SceneNode, _depth: usize) {
+            self.left.push(node.name.clone().unwrap_or_default());
+        }
+    }
+
+    #[test]
+    fn walk_mut_marks_moved_nodes_dirty() {
+        let mut graph = tree();
+        graph.update_transforms();
+        let root = graph.root();
+        let mut mover = Mover::default();
+        graph.walk_mut(root, &mut mover);
+
+        let dirty = |name: &str| graph[graph.lookup(name).unwrap()].is_dirty();
+        assert!(dirty("a"));
+        assert!(!dirty("a/a1") && !dirty("b") && !dirty("b/b1"));
+        // The children are handed where their parent was moved to
+        assert_near(mover.a1_origin.unwrap(), glm::vec3(1.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn walk_mut_skips_children_when_told_to() {
+        let mut graph = tree();
+        let root = graph.root();
+        let mut mover = Mover::default();
+        graph.walk_mut(root, &mut mover);
+        assert_eq!(mover.visited, ["", "a", "a1", "a2", "b", "b1"]);
+        assert_eq!(mover.left, ["a1", "a2", "a", "b1", "b", ""]);
+    }
+
+    // Hands "b1" another mesh and gives "a1" bounds of its own
+    struct Refitter {
+        mesh   : MeshHandle,
+        bounds : Bounds,
+    }
+
+    impl SceneVisitor for Refitter {
+        fn visit(&mut self, _id: NodeId, node: &mut SceneNode, _world: &glm::Mat4, _depth: usize) -> bool {
+            match node.name.as_deref() {
+                Some("b1") => node.mesh = Some(self.mesh.clone()),
+                Some("a1") => node.set_bounds(Some(self.bounds)),
+                _ => {},
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn walk_mut_marks_nodes_with_new_bounds_dirty() {
+        let mut registry = MeshRegistry::without_gpu();
+        let mesh = registry.load("triangle", || {
+            let mut mesh = Mesh::empty();
+            mesh.vertices = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+            mesh.indices = vec![0, 1, 2];
+            mesh.index_count = 3;
+            Ok(mesh)
+        }).unwrap();
+        let bounds = Bounds::from_points(&[-1.0, -1.0, -1.0, 1.0, 1.0, 1.0]).unwrap();
+
+        let mut graph = tree();
+        graph.update_transforms();
+        let root = graph.root();
+        graph.walk_mut(root, &mut Refitter { mesh: mesh.clone(), bounds });
+
+        let (a1, b1) = (graph.lookup("a/a1").unwrap(), graph.lookup("b/b1").unwrap());
+        assert!(graph[a1].is_dirty() && graph[b1].is_dirty());
+        assert!(!graph[graph.lookup("a").unwrap()].is_dirty());
+        assert_eq!(graph[a1].bounds(), Some(&bounds));
+        assert_eq!(graph[b1].bounds(), mesh.bounds.as_ref());
+
+        graph.update_transforms();
+        let a1_center = graph[a1].world_bounds().unwrap().aabb.center();
+        assert_near(a1_center, glm::vec3(1.0, 1.0, 0.0));
+        assert!(graph[root].subtree_bounds().unwrap().aabb.contains(&a1_center));
+        assert!(graph[b1].world_bounds().is_some());
+    }
+}
@@ -1,5 +1,4 @@
 use std::ffi::CString;
-use std::{mem, os::raw::c_void};
 
 pub unsafe fn get_gl_string(name: gl::types::GLenum) -> String {
     std::ffi::CStr::from_ptr(gl::GetString(name) as *mut i8).to_string_lossy().to_string()